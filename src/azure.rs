@@ -0,0 +1,78 @@
+//! Provides structures to annotate Azure Pipelines
+
+use crate::github::{Annotation, AnnotationKind};
+use std::fmt::{self, Display, Formatter};
+
+/// A `task.logissue` logging command
+///
+/// See the [Azure Pipelines documentation](https://learn.microsoft.com/en-us/azure/devops/pipelines/scripts/logging-commands#logissue-log-an-error-or-warning).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LogIssue<'a, 's>(pub(crate) &'a Annotation<'s>);
+impl<'a, 's> Display for LogIssue<'a, 's> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let annotation = self.0;
+
+		write!(
+			f,
+			"##vso[task.logissue type={}",
+			match annotation.kind {
+				AnnotationKind::Error => "error",
+				AnnotationKind::Warning | AnnotationKind::Notice => "warning",
+			},
+		)?;
+		if annotation.has_location() {
+			write!(
+				f,
+				";sourcepath={};linenumber={}",
+				escape_property(&annotation.file),
+				annotation.line,
+			)?;
+			if let Some(col) = annotation.col {
+				write!(f, ";columnnumber={col}")?;
+			}
+		}
+		write!(f, "]{}", escape_message(annotation.message.trim()))
+	}
+}
+
+/// Escapes the value of a property of a logging command
+fn escape_property(value: &str) -> String {
+	escape_message(value)
+		.replace(';', "%3B")
+		.replace(']', "%5D")
+}
+
+/// Escapes the message of a logging command
+fn escape_message(message: &str) -> String {
+	message
+		.replace('%', "%AZP25")
+		.replace('\r', "%0D")
+		.replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::borrow::Cow;
+
+	#[test]
+	fn log_issue() {
+		let annotation = Annotation {
+			kind: AnnotationKind::Error,
+			file: Cow::Borrowed("src/a;b.rs"),
+			line: 3,
+			end_line: Some(3),
+			col: Some(5),
+			end_column: Some(10),
+			title: None,
+			message: Cow::Borrowed("mismatched types\n100% sure"),
+			package: None,
+			code: None,
+		};
+
+		assert_eq!(
+			LogIssue(&annotation).to_string(),
+			"##vso[task.logissue type=error;sourcepath=src/a%3Bb.rs;linenumber=3;columnnumber=5]mismatched types%0A100%AZP25 sure"
+		);
+	}
+}
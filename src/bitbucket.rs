@@ -0,0 +1,129 @@
+//! Provides structures to annotate Bitbucket Pipelines
+
+use crate::github::{Annotation, AnnotationKind};
+use serde::Serialize;
+
+/// A [Code Insights](https://support.atlassian.com/bitbucket-cloud/docs/code-insights/) report
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CodeInsightsReport<'a> {
+	/// Annotations of the report
+	annotations: Vec<CodeInsightsAnnotation<'a>>,
+}
+impl<'a, 's: 'a> FromIterator<&'a Annotation<'s>> for CodeInsightsReport<'a> {
+	#[inline]
+	fn from_iter<I: IntoIterator<Item = &'a Annotation<'s>>>(iter: I) -> Self {
+		Self {
+			annotations: iter.into_iter().map(CodeInsightsAnnotation::from).collect(),
+		}
+	}
+}
+
+/// An annotation of a [`CodeInsightsReport`]
+#[derive(Debug, Clone, Copy, Serialize)]
+struct CodeInsightsAnnotation<'a> {
+	/// File to annotate
+	#[serde(skip_serializing_if = "Option::is_none")]
+	path: Option<&'a str>,
+	/// Line to annotate (1-based)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	line: Option<usize>,
+	/// Short summary of the annotation
+	summary: &'a str,
+	/// Annotation message
+	message: &'a str,
+	/// Severity of the annotation
+	severity: Severity,
+	/// Type of the annotation
+	annotation_type: AnnotationType,
+}
+impl<'a, 's: 'a> From<&'a Annotation<'s>> for CodeInsightsAnnotation<'a> {
+	#[inline]
+	fn from(annotation: &'a Annotation<'s>) -> Self {
+		let message = annotation.message.trim();
+		Self {
+			path: annotation.has_location().then_some(&*annotation.file),
+			line: annotation.has_location().then_some(annotation.line),
+			summary: annotation
+				.title
+				.as_deref()
+				.unwrap_or_else(|| message.lines().next().unwrap_or_default()),
+			message,
+			severity: annotation.kind.into(),
+			annotation_type: annotation.kind.into(),
+		}
+	}
+}
+
+#[allow(clippy::missing_docs_in_private_items)]
+/// Severity of a [`CodeInsightsAnnotation`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum Severity {
+	Low,
+	Medium,
+	High,
+}
+impl From<AnnotationKind> for Severity {
+	#[inline]
+	fn from(kind: AnnotationKind) -> Self {
+		match kind {
+			AnnotationKind::Notice => Self::Low,
+			AnnotationKind::Warning => Self::Medium,
+			AnnotationKind::Error => Self::High,
+		}
+	}
+}
+
+#[allow(clippy::missing_docs_in_private_items)]
+/// Type of a [`CodeInsightsAnnotation`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum AnnotationType {
+	Bug,
+	CodeSmell,
+}
+impl From<AnnotationKind> for AnnotationType {
+	#[inline]
+	fn from(kind: AnnotationKind) -> Self {
+		match kind {
+			AnnotationKind::Error => Self::Bug,
+			AnnotationKind::Warning | AnnotationKind::Notice => Self::CodeSmell,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::borrow::Cow;
+
+	#[test]
+	fn code_insights_report() {
+		let annotation = Annotation {
+			kind: AnnotationKind::Warning,
+			file: Cow::Borrowed("src/main.rs"),
+			line: 3,
+			end_line: Some(3),
+			col: Some(5),
+			end_column: Some(10),
+			title: None,
+			message: Cow::Borrowed("unused variable\n --> src/main.rs:3:5\n"),
+			package: None,
+			code: None,
+		};
+
+		assert_eq!(
+			serde_json::to_value(CodeInsightsReport::from_iter([&annotation])).unwrap(),
+			serde_json::json!({
+				"annotations": [{
+					"path": "src/main.rs",
+					"line": 3,
+					"summary": "unused variable",
+					"message": "unused variable\n --> src/main.rs:3:5",
+					"severity": "MEDIUM",
+					"annotation_type": "CODE_SMELL",
+				}],
+			})
+		);
+	}
+}
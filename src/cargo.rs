@@ -1,15 +1,149 @@
 //! Provides structures to parse Cargo JSON data
 
 use serde::Deserialize;
-use std::borrow::Cow;
+use std::{
+	borrow::Cow,
+	io::{self, BufRead, Bytes},
+};
 
 /// Message outputted by Cargo
 #[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "reason", content = "message", rename_all = "kebab-case")]
+#[serde(tag = "reason", rename_all = "kebab-case")]
 pub(crate) enum CargoMessage<'c> {
 	/// Message outputted by rustc
 	#[serde(borrow)]
-	CompilerMessage(Diagnostic<'c>),
+	CompilerMessage(CompilerMessage<'c>),
+}
+
+/// Message outputted by rustc while compiling a package
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct CompilerMessage<'c> {
+	/// Opaque identifier of the package being compiled
+	pub(crate) package_id: &'c str,
+	/// Diagnostic emitted by rustc
+	#[serde(borrow)]
+	pub(crate) message: Diagnostic<'c>,
+}
+
+/// Iterator over the JSON objects of a stream, read one at a time
+///
+/// The objects may be concatenated, spread over several lines, or nested in arrays (whose brackets and commas are skipped).
+/// Any text outside of the objects (e.g. the `Compiling` lines of a merged log) is skipped too.
+#[derive(Debug)]
+pub(crate) struct JsonObjects<R> {
+	/// Bytes of the stream
+	bytes: Bytes<R>,
+}
+impl<R: BufRead> JsonObjects<R> {
+	/// Reads the JSON objects of `reader`
+	#[inline]
+	pub(crate) fn new(reader: R) -> Self {
+		Self {
+			bytes: reader.bytes(),
+		}
+	}
+}
+impl<R: BufRead> Iterator for JsonObjects<R> {
+	type Item = io::Result<String>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			match self.bytes.next()? {
+				Ok(b'{') => break,
+				Ok(_byte) => {}
+				Err(err) => return Some(Err(err)),
+			}
+		}
+
+		let mut object = vec![b'{'];
+		let mut depth = 1_usize;
+		let mut in_string = false;
+		let mut escaped = false;
+		// A truncated object is returned as is, and fails to be parsed later on
+		while depth > 0 {
+			let byte = match self.bytes.next() {
+				Some(Ok(byte)) => byte,
+				Some(Err(err)) => return Some(Err(err)),
+				None => break,
+			};
+			object.push(byte);
+			if in_string {
+				match byte {
+					_ if escaped => escaped = false,
+					b'\\' => escaped = true,
+					b'"' => in_string = false,
+					_ => {}
+				}
+			} else {
+				match byte {
+					b'"' => in_string = true,
+					b'{' => depth += 1,
+					b'}' => depth -= 1,
+					_ => {}
+				}
+			}
+		}
+		Some(Ok(String::from_utf8_lossy(&object).into_owned()))
+	}
+}
+
+/// Message outputted by Cargo about a built artifact
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub(crate) enum ArtifactMessage<'c> {
+	/// A crate was compiled
+	CompilerArtifact {
+		/// Opaque identifier of the package of the crate
+		package_id: &'c str,
+	},
+}
+
+/// Returns the name of the package identified by `package_id`
+///
+/// Both the current (`path+file:///path/to/a#0.1.0` or `registry+https://...#a@0.1.0`)
+/// and the legacy (`a 0.1.0 (path+file:///path/to/a)`) formats are recognized.
+pub(crate) fn package_name(package_id: &str) -> &str {
+	if let Some((name, _)) = package_id.split_once(' ') {
+		return name;
+	}
+	match package_id.rsplit_once('#') {
+		Some((_, fragment)) if fragment.contains('@') => {
+			fragment.split('@').next().unwrap_or(fragment)
+		}
+		Some((url, _version)) => url.rsplit('/').next().unwrap_or(url),
+		None => package_id,
+	}
+}
+
+/// Returns the directory of the package identified by `package_id`, if it is a local package
+///
+/// Both the current (`path+file:///path/to/a#0.1.0`)
+/// and the legacy (`a 0.1.0 (path+file:///path/to/a)`) formats are recognized.
+pub(crate) fn package_dir(package_id: &str) -> Option<&str> {
+	let (_, dir) = package_id.split_once("path+file://")?;
+	dir.split(['#', ')']).next()
+}
+
+/// Returns an identity of the package identified by `package_id` that does not depend on where it is checked out
+///
+/// Local packages are identified by their names and versions (e.g. `a@0.1.0`),
+/// so that the diagnostics reported by several machines (e.g. the jobs of a build matrix) are told to be the same.
+/// Other packages keep their IDs.
+pub(crate) fn package_identity(package_id: &str) -> Cow<'_, str> {
+	if package_dir(package_id).is_none() {
+		return Cow::Borrowed(package_id);
+	}
+	let version = match package_id.split_once(' ') {
+		Some((_name, rest)) => rest.split(' ').next(),
+		None => package_id
+			.rsplit_once('#')
+			.and_then(|(_url, fragment)| fragment.rsplit('@').next()),
+	};
+	let name = package_name(package_id);
+	match version {
+		Some(version) => Cow::Owned(format!("{name}@{version}")),
+		None => Cow::Borrowed(name),
+	}
 }
 
 /// rustc's diagnostic message
@@ -17,6 +151,9 @@ pub(crate) enum CargoMessage<'c> {
 pub(crate) struct Diagnostic<'c> {
 	/// Primary message
 	pub(crate) message: &'c str,
+	/// Code identifying the kind of diagnostic
+	#[serde(borrow)]
+	pub(crate) code: Option<DiagnosticCode<'c>>,
 	/// Severity of the diagnostic
 	pub(crate) level: DiagnosticLevel,
 	/// Locations in the source code of this diagnostic
@@ -27,6 +164,35 @@ pub(crate) struct Diagnostic<'c> {
 	pub(crate) rendered: Option<Cow<'c, str>>,
 }
 
+/// Returns the location of a diagnostic, as `(file, line, column)`, from the `-->` line of its rendered form
+pub(crate) fn rendered_location(rendered: &str) -> Option<(&str, usize, usize)> {
+	rendered.lines().find_map(|line| {
+		let location = line.trim_start().strip_prefix("--> ")?;
+		parse_location(location.trim_end())
+	})
+}
+
+/// Parses a location of the form `file:line:column`
+fn parse_location(location: &str) -> Option<(&str, usize, usize)> {
+	let mut parts = location.rsplitn(3, ':');
+	let column = parts.next()?.parse().ok()?;
+	let line = parts.next()?.parse().ok()?;
+	Some((parts.next()?, line, column))
+}
+
+/// Code of a [`Diagnostic`]
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub(crate) struct DiagnosticCode<'c> {
+	/// Unique string identifying the diagnostic (e.g. `E0308` or `clippy::needless_return`)
+	pub(crate) code: &'c str,
+}
+
+/// Returns `true` if `code` is an error code of rustc (e.g. `E0308`), rather than the name of a lint
+pub(crate) fn is_error_code(code: &str) -> bool {
+	code.strip_prefix('E')
+		.is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+}
+
 /// Severity of a [`Diagnostic`]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -44,6 +210,9 @@ pub(crate) enum DiagnosticLevel {
 	/// Indicates a bug within the compiler
 	#[serde(rename = "error: internal compiler error")]
 	InternalCompilerError,
+	/// A level unknown to this version (e.g. introduced by a newer rustc), annotated as a notice
+	#[serde(other)]
+	Unknown,
 }
 
 /// The location of a diagnostic in the source code
@@ -64,3 +233,76 @@ pub(crate) struct DiagnosticSpan<'c> {
 	/// This span is the "primary" span
 	pub(crate) is_primary: bool,
 }
+
+/// Message outputted by libtest with `--format json`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub(crate) enum TestMessage<'c> {
+	/// Event about a single test
+	#[serde(borrow)]
+	Test(TestEvent<'c>),
+}
+
+/// Event about a single test
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum TestEvent<'c> {
+	/// The test failed
+	Failed {
+		/// Name of the test
+		name: Cow<'c, str>,
+		/// Captured output of the test
+		#[serde(borrow)]
+		stdout: Option<Cow<'c, str>>,
+	},
+}
+impl<'c> TestEvent<'c> {
+	/// Returns the location where the test panicked, as `(file, line, column)`
+	///
+	/// Both the current (`panicked at src/lib.rs:1:5:`)
+	/// and the legacy (`panicked at 'message', src/lib.rs:1:5`) formats are recognized.
+	pub(crate) fn panic_location(&self) -> Option<(&str, usize, usize)> {
+		let location = self.stdout()?.lines().find_map(|line| {
+			let location = line.split_once("panicked at ")?.1;
+			Some(if location.starts_with('\'') {
+				location.rsplit_once("', ")?.1
+			} else {
+				location.strip_suffix(':').unwrap_or(location)
+			})
+		})?;
+
+		parse_location(location)
+	}
+
+	/// Returns the captured output of the test
+	#[inline]
+	pub(crate) fn stdout(&self) -> Option<&str> {
+		let Self::Failed { stdout, .. } = self;
+		stdout.as_deref()
+	}
+
+	/// Returns the panic message and what follows it in the captured output
+	pub(crate) fn panic_message(&self) -> Option<&str> {
+		let stdout = self.stdout()?;
+		let panic = stdout.find("panicked at ")?;
+		let start = stdout[..panic].rfind('\n').map_or(0, |newline| newline + 1);
+		Some(&stdout[start..])
+	}
+}
+
+/// Output of `cargo metadata --format-version 1`
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Metadata<'c> {
+	/// Packages of the workspace
+	#[serde(borrow)]
+	pub(crate) packages: Vec<Package<'c>>,
+}
+
+/// Package described by [`Metadata`]
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub(crate) struct Package<'c> {
+	/// Name of the package
+	pub(crate) name: &'c str,
+	/// Opaque identifier of the package
+	pub(crate) id: &'c str,
+}
@@ -1,64 +1,205 @@
 //! Provides structures and functions to annotate GitHub Actions
 
-use crate::cargo::{CargoMessage, DiagnosticLevel};
+use crate::cargo::{
+	package_identity, CargoMessage, CompilerMessage, DiagnosticLevel, TestEvent, TestMessage,
+};
 use serde::Serialize;
 use std::{
 	borrow::Cow,
 	cmp::Ordering,
+	error::Error,
 	fmt::{self, Display, Formatter},
 	path::Path,
 };
 
+/// Maximum length of a workflow command (in bytes), beyond which GitHub may drop it
+///
+/// This is a conservative bound, well below the length of the lines read by the runner.
+pub(crate) const MAX_COMMAND_LEN: usize = 64 * 1024;
+
 /// An annotation command
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Annotation<'s> {
 	/// Kind of annotation
 	pub(crate) kind: AnnotationKind,
 	/// File to annotate
-	file: Cow<'s, str>,
+	pub(crate) file: Cow<'s, str>,
 	/// Start of the lines to annotate (1-based, inclusive)
-	line: usize,
+	pub(crate) line: usize,
 	/// End of the lines to annotate (1-based)
-	end_line: Option<usize>,
+	pub(crate) end_line: Option<usize>,
 	/// Start of the columns to annotate (1-based, inclusive)
-	col: Option<usize>,
+	pub(crate) col: Option<usize>,
 	/// End of the lines to annotate (1-based)
-	end_column: Option<usize>,
+	pub(crate) end_column: Option<usize>,
 	/// Annotation title
-	title: Option<Cow<'s, str>>,
+	pub(crate) title: Option<Cow<'s, str>>,
 	/// Annotation message
-	message: Cow<'s, str>,
+	pub(crate) message: Cow<'s, str>,
+	/// Opaque identifier of the package the annotation originates from
+	///
+	/// It is not emitted, but it keeps apart the annotations of distinct packages on the same relative path.
+	/// Local packages are identified [regardless of their location](crate::cargo::package_identity).
+	pub(crate) package: Option<Cow<'s, str>>,
+	/// Code of the diagnostic the annotation originates from (not emitted)
+	pub(crate) code: Option<Cow<'s, str>>,
 }
 impl<'c> TryFrom<CargoMessage<'c>> for Annotation<'c> {
 	type Error = &'static str;
 
 	fn try_from(message: CargoMessage<'c>) -> Result<Self, Self::Error> {
 		match message {
-			CargoMessage::CompilerMessage(message) => {
+			CargoMessage::CompilerMessage(CompilerMessage {
+				package_id,
+				message,
+			}) => {
 				let primary_span = message
 					.spans
 					.iter()
 					.find(|span| span.is_primary)
 					.ok_or("Missing primary span")?;
 
+				let single_line = primary_span.line_start == primary_span.line_end;
 				Ok(Self {
 					kind: message.level.into(),
 					file: Cow::Borrowed(primary_span.file_name),
 					line: primary_span.line_start,
 					end_line: Some(primary_span.line_end),
-					col: Some(primary_span.column_start),
-					end_column: Some(primary_span.column_end),
+					col: single_line.then_some(primary_span.column_start),
+					// Zero-width spans (e.g. at the end of a file) only have a start column
+					end_column: (single_line
+						&& primary_span.column_end > primary_span.column_start)
+						.then_some(primary_span.column_end),
 					title: message
 						.rendered
 						.as_ref()
+						// The title would be redundant if the rendered message already starts with it,
+						// or if it is rendered in the short form (`--message-format=json-diagnostic-short`)
+						.filter(|rendered| {
+							let rendered = rendered.trim();
+							let short_form = !rendered.contains('\n');
+							!(strip_level(rendered).starts_with(message.message.trim())
+								|| (short_form && rendered.contains(message.message.trim())))
+						})
 						.map(|_rendered| Cow::Borrowed(message.message)),
 					message: message.rendered.unwrap_or(Cow::Borrowed(message.message)),
+					package: Some(package_identity(package_id)),
+					code: message.code.map(|code| Cow::Borrowed(code.code)),
+				})
+			}
+		}
+	}
+}
+/// Strips the `level[code]: ` header from the start of a diagnostic rendered by rustc (e.g. `error[E0308]: `)
+fn strip_level(rendered: &str) -> &str {
+	match rendered.split_once(": ") {
+		Some((header, rest))
+			if header.split('[').next().is_some_and(|level| {
+				!level.is_empty() && level.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+			}) =>
+		{
+			rest
+		}
+		_ => rendered,
+	}
+}
+
+impl<'c> TryFrom<&'c TestMessage<'c>> for Annotation<'c> {
+	type Error = &'static str;
+
+	fn try_from(message: &'c TestMessage<'c>) -> Result<Self, Self::Error> {
+		match message {
+			TestMessage::Test(event @ TestEvent::Failed { name, .. }) => {
+				let (file, line, col) = event.panic_location().ok_or("Missing panic location")?;
+
+				Ok(Self {
+					kind: AnnotationKind::Error,
+					file: Cow::Borrowed(file),
+					line,
+					end_line: None,
+					col: Some(col),
+					end_column: None,
+					title: Some(Cow::Owned(format!("test {name} failed"))),
+					message: Cow::Borrowed(event.panic_message().ok_or("Missing panic message")?),
+					package: None,
+					code: None,
 				})
 			}
 		}
 	}
 }
 impl<'s> Annotation<'s> {
+	/// Returns `true` if `self` is attached to a file
+	///
+	/// Annotations with an empty file (e.g. from synthetic diagnostics) are emitted without location.
+	#[inline]
+	pub(crate) fn has_location(&self) -> bool {
+		!self.file.is_empty()
+	}
+
+	/// Checks that `self` satisfies the constraints of GitHub on workflow commands
+	///
+	/// The location is not checked if `self` has [none](Self::has_location).
+	///
+	/// # Errors
+	/// Returns the first constraint that is violated.
+	pub(crate) fn validate(&self) -> Result<(), AnnotationError> {
+		if self.has_location() {
+			if self.line == 0 {
+				return Err(AnnotationError::NullLine);
+			}
+			if self.end_line.is_some_and(|end_line| end_line < self.line) {
+				return Err(AnnotationError::EndLineBeforeLine);
+			}
+			if let Some(col) = self.col {
+				if self.end_line.is_some_and(|end_line| end_line != self.line) {
+					return Err(AnnotationError::ColumnOnMultipleLines);
+				}
+				if self.end_column.is_some_and(|end_column| end_column <= col) {
+					return Err(AnnotationError::EndColumnBeforeColumn);
+				}
+			}
+		}
+		if self.message.trim().is_empty() {
+			return Err(AnnotationError::EmptyMessage);
+		}
+		Ok(())
+	}
+
+	/// Returns the length of `self` as a workflow command (in bytes)
+	#[inline]
+	pub(crate) fn command_len(&self) -> usize {
+		self.to_string().len()
+	}
+
+	/// Truncates the message of `self`, appending `…`, so that its workflow command is at most `max_len` bytes long
+	///
+	/// The properties (location and title) are kept intact, only the message is truncated.
+	pub(crate) fn truncate_to_command_len(&mut self, max_len: usize) {
+		let len = self.command_len();
+		if len <= max_len {
+			return;
+		}
+		let message = self.message.trim();
+		let properties_len = len - escape_data(message).len();
+		let budget = max_len.saturating_sub(properties_len + '…'.len_utf8());
+
+		let mut end = 0;
+		let mut escaped_len = 0;
+		for (index, c) in message.char_indices() {
+			escaped_len += match c {
+				// Escaped as `%XX`
+				'%' | '\n' | '\r' => 3,
+				c => c.len_utf8(),
+			};
+			if escaped_len > budget {
+				break;
+			}
+			end = index + c.len_utf8();
+		}
+		self.message = Cow::Owned(format!("{}…", message[..end].trim_end()));
+	}
+
 	/// Clones `self` such that all strings are owned
 	#[inline]
 	pub(crate) fn to_owned(&self) -> Annotation<'static> {
@@ -74,6 +215,11 @@ impl<'s> Annotation<'s> {
 				.clone()
 				.map(|title| Cow::Owned(title.into_owned())),
 			message: Cow::Owned(self.message.clone().into_owned()),
+			package: self
+				.package
+				.clone()
+				.map(|package| Cow::Owned(package.into_owned())),
+			code: self.code.clone().map(|code| Cow::Owned(code.into_owned())),
 		}
 	}
 }
@@ -91,37 +237,86 @@ impl<'s> Ord for Annotation<'s> {
 			.then_with(|| self.line.cmp(&other.line))
 			.then_with(|| self.col.cmp(&other.col))
 			.then_with(|| self.kind.cmp(&other.kind).reverse())
+			.then_with(|| self.package.cmp(&other.package))
+			// Without a location, only the text tells distinct annotations apart
+			.then_with(|| {
+				if self.has_location() {
+					Ordering::Equal
+				} else {
+					(&self.title, &self.message).cmp(&(&other.title, &other.message))
+				}
+			})
 	}
 }
 impl<'s> Display for Annotation<'s> {
 	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
 		write!(f, "::")?;
 		self.kind.serialize(&mut *f)?;
-		write!(f, " file={},line={}", self.file, self.line)?;
-		if let Some(end_line) = self.end_line {
-			write!(f, ",endLine={end_line}")?;
-		}
-		if let Some(col) = self.col {
-			write!(f, ",col={col}")?;
-			if let Some(end_column) = self.end_column {
-				write!(f, ",endColumn={end_column}")?;
+		let mut separator = ' ';
+		if self.has_location() {
+			write!(
+				f,
+				" file={},line={}",
+				escape_property(&self.file),
+				self.line
+			)?;
+			if let Some(end_line) = self.end_line {
+				write!(f, ",endLine={end_line}")?;
+			}
+			if let Some(col) = self.col {
+				write!(f, ",col={col}")?;
+				if let Some(end_column) = self.end_column {
+					write!(f, ",endColumn={end_column}")?;
+				}
 			}
+			separator = ',';
 		}
 		if let Some(title) = &self.title {
-			write!(f, ",title={title}")?;
+			write!(f, "{separator}title={}", escape_property(title))?;
 		}
-		write!(
-			f,
-			"::{}",
-			self.message
-				.trim()
-				.replace('%', "%25")
-				.replace('\n', "%0A")
-				.replace('\r', "%0D")
-		)
+		write!(f, "::{}", escape_data(self.message.trim()))
 	}
 }
 
+/// Escapes the data of a workflow command
+fn escape_data(data: &str) -> String {
+	data.replace('%', "%25")
+		.replace('\n', "%0A")
+		.replace('\r', "%0D")
+}
+
+/// Escapes the value of a property of a workflow command
+fn escape_property(value: &str) -> String {
+	escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Constraint violated by an [`Annotation`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AnnotationError {
+	/// The start line is 0
+	NullLine,
+	/// The end line is before the start line
+	EndLineBeforeLine,
+	/// Columns are set but the annotation spans several lines
+	ColumnOnMultipleLines,
+	/// The end column is not after the start column
+	EndColumnBeforeColumn,
+	/// The message is empty after trimming
+	EmptyMessage,
+}
+impl Display for AnnotationError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::NullLine => "line is 0",
+			Self::EndLineBeforeLine => "end line is before start line",
+			Self::ColumnOnMultipleLines => "columns on a multi-line annotation",
+			Self::EndColumnBeforeColumn => "end column is not after start column",
+			Self::EmptyMessage => "empty message",
+		})
+	}
+}
+impl Error for AnnotationError {}
+
 #[allow(clippy::missing_docs_in_private_items)]
 /// Kind of annotation
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
@@ -142,6 +337,16 @@ impl From<DiagnosticLevel> for AnnotationKind {
 	}
 }
 impl AnnotationKind {
+	/// Returns the name of the annotation kind, as used in workflow commands
+	#[inline]
+	pub(crate) const fn name(&self) -> &'static str {
+		match self {
+			Self::Notice => "notice",
+			Self::Warning => "warning",
+			Self::Error => "error",
+		}
+	}
+
 	/// Returns the emoji associated with the annotation kind
 	#[inline]
 	pub(crate) const fn emoji(&self) -> &'static str {
@@ -157,3 +362,228 @@ impl Display for AnnotationKind {
 		write!(f, "{} {self:?}", self.emoji())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn annotation() -> Annotation<'static> {
+		Annotation {
+			kind: AnnotationKind::Warning,
+			file: Cow::Borrowed("src/main.rs"),
+			line: 3,
+			end_line: Some(3),
+			col: Some(5),
+			end_column: Some(10),
+			title: None,
+			message: Cow::Borrowed("unused variable"),
+			package: None,
+			code: None,
+		}
+	}
+
+	fn compiler_message(rendered: &str) -> String {
+		serde_json::json!({
+			"reason": "compiler-message",
+			"package_id": "a",
+			"message": {
+				"message": "unused variable: `x`",
+				"level": "warning",
+				"spans": [{
+					"file_name": "src/main.rs",
+					"line_start": 3,
+					"line_end": 3,
+					"column_start": 5,
+					"column_end": 6,
+					"is_primary": true,
+				}],
+				"rendered": rendered,
+			},
+		})
+		.to_string()
+	}
+
+	#[test]
+	fn redundant_title() {
+		let message = compiler_message(
+			"warning: unused variable: `x`
+ --> src/main.rs:3:5
+  |
+3 |     let x = 1;
+  |         ^ help: if this is intentional, prefix it with an underscore: `_x`
+  |
+  = note: `#[warn(unused_variables)]` on by default
+
+",
+		);
+		let annotation =
+			Annotation::try_from(serde_json::from_str::<CargoMessage>(&message).unwrap()).unwrap();
+		assert_eq!(annotation.title, None);
+	}
+
+	#[test]
+	fn distinct_title() {
+		let message = compiler_message("warning: unused variable\n --> src/main.rs:3:5\n");
+		let annotation =
+			Annotation::try_from(serde_json::from_str::<CargoMessage>(&message).unwrap()).unwrap();
+		assert_eq!(annotation.title.as_deref(), Some("unused variable: `x`"));
+	}
+
+	#[test]
+	fn strip_level() {
+		assert_eq!(
+			super::strip_level("error[E0308]: mismatched types"),
+			"mismatched types"
+		);
+		assert_eq!(
+			super::strip_level("warning: unused variable: `x`"),
+			"unused variable: `x`"
+		);
+		assert_eq!(
+			super::strip_level("src/main.rs:3:5: warning: unused variable"),
+			"src/main.rs:3:5: warning: unused variable"
+		);
+	}
+
+	#[test]
+	fn short_rendered() {
+		let message = compiler_message("src/main.rs:3:5: warning: unused variable: `x`\n");
+		let annotation =
+			Annotation::try_from(serde_json::from_str::<CargoMessage>(&message).unwrap()).unwrap();
+		assert_eq!(annotation.title, None);
+		assert_eq!(
+			annotation.message.trim(),
+			"src/main.rs:3:5: warning: unused variable: `x`"
+		);
+	}
+
+	#[test]
+	fn zero_width_span() {
+		let mut message: serde_json::Value = serde_json::from_str(&compiler_message(
+			"warning: unused variable: `x`\n --> src/main.rs:3:5\n",
+		))
+		.unwrap();
+		message["message"]["spans"][0]["column_end"] = 5.into();
+		let message = message.to_string();
+		let annotation =
+			Annotation::try_from(serde_json::from_str::<CargoMessage>(&message).unwrap()).unwrap();
+		assert_eq!((annotation.col, annotation.end_column), (Some(5), None));
+		assert_eq!(annotation.validate(), Ok(()));
+	}
+
+	#[test]
+	fn escape_title() {
+		let annotation = Annotation {
+			title: Some(Cow::Borrowed("test tests::a, failed")),
+			..annotation()
+		};
+		assert_eq!(
+			annotation.to_string(),
+			"::warning file=src/main.rs,line=3,endLine=3,col=5,endColumn=10,title=test tests%3A%3Aa%2C failed::unused variable"
+		);
+	}
+
+	#[test]
+	fn truncate_to_command_len() {
+		let mut annotation = annotation();
+		annotation.truncate_to_command_len(MAX_COMMAND_LEN);
+		assert_eq!(annotation, self::annotation());
+
+		let message = "warning: unused variable\n".to_owned() + &"é 100%\n".repeat(MAX_COMMAND_LEN);
+		annotation.message = Cow::Owned(message);
+		annotation.title = Some(Cow::Borrowed("unused variable"));
+		assert!(annotation.command_len() > MAX_COMMAND_LEN);
+
+		annotation.truncate_to_command_len(MAX_COMMAND_LEN);
+		let command = annotation.to_string();
+		assert!(command.len() <= MAX_COMMAND_LEN);
+		assert!(command.len() > MAX_COMMAND_LEN - 16);
+		assert!(command.starts_with(
+			"::warning file=src/main.rs,line=3,endLine=3,col=5,endColumn=10,title=unused variable::warning: unused variable%0Aé 100%25%0A"
+		));
+		assert!(command.ends_with('…'));
+		assert!(!command.contains('\n'));
+		assert_eq!(annotation.validate(), Ok(()));
+	}
+
+	#[test]
+	fn validate_ok() {
+		assert_eq!(annotation().validate(), Ok(()));
+	}
+
+	#[test]
+	fn validate_empty_file() {
+		let annotation = Annotation {
+			file: Cow::Borrowed(""),
+			line: 0,
+			..annotation()
+		};
+		assert_eq!(annotation.validate(), Ok(()));
+		assert_eq!(annotation.to_string(), "::warning::unused variable");
+		let annotation = Annotation {
+			title: Some(Cow::Borrowed("title")),
+			..annotation
+		};
+		assert_eq!(
+			annotation.to_string(),
+			"::warning title=title::unused variable"
+		);
+	}
+
+	#[test]
+	fn validate_null_line() {
+		let annotation = Annotation {
+			line: 0,
+			end_line: None,
+			..annotation()
+		};
+		assert_eq!(annotation.validate(), Err(AnnotationError::NullLine));
+	}
+
+	#[test]
+	fn validate_end_line_before_line() {
+		let annotation = Annotation {
+			end_line: Some(2),
+			col: None,
+			end_column: None,
+			..annotation()
+		};
+		assert_eq!(
+			annotation.validate(),
+			Err(AnnotationError::EndLineBeforeLine)
+		);
+	}
+
+	#[test]
+	fn validate_column_on_multiple_lines() {
+		let annotation = Annotation {
+			end_line: Some(4),
+			..annotation()
+		};
+		assert_eq!(
+			annotation.validate(),
+			Err(AnnotationError::ColumnOnMultipleLines)
+		);
+	}
+
+	#[test]
+	fn validate_end_column_before_column() {
+		let annotation = Annotation {
+			end_column: Some(5),
+			..annotation()
+		};
+		assert_eq!(
+			annotation.validate(),
+			Err(AnnotationError::EndColumnBeforeColumn)
+		);
+	}
+
+	#[test]
+	fn validate_empty_message() {
+		let annotation = Annotation {
+			message: Cow::Borrowed(" \n "),
+			..annotation()
+		};
+		assert_eq!(annotation.validate(), Err(AnnotationError::EmptyMessage));
+	}
+}
@@ -0,0 +1,113 @@
+//! Provides a minimal matcher of glob patterns
+
+use std::{
+	io,
+	path::{Path, PathBuf},
+};
+
+/// Returns `true` if `path` matches the glob `pattern`
+///
+/// - `*` matches any sequence of characters, except `/`
+/// - `**` matches any sequence of characters (`**/` also matches no directory at all)
+/// - `?` matches any single character, except `/`
+/// - any other character matches itself
+pub(crate) fn matches(pattern: &str, path: &str) -> bool {
+	matches_chars(
+		&pattern.chars().collect::<Vec<_>>(),
+		&path.chars().collect::<Vec<_>>(),
+	)
+}
+
+/// Returns the paths of the files matching the glob `pattern`, in lexicographic order
+///
+/// Only the directory named by the components of `pattern` before its first wildcard is walked.
+///
+/// # Errors
+/// Returns any error that occurred while reading the directories.
+pub(crate) fn expand(pattern: &str) -> io::Result<Vec<PathBuf>> {
+	let components = pattern.split('/').collect::<Vec<_>>();
+	let literal = components
+		.iter()
+		.take_while(|component| !component.contains(['*', '?']))
+		.count();
+	if literal == components.len() {
+		let path = Path::new(pattern);
+		return Ok(path
+			.is_file()
+			.then(|| path.to_path_buf())
+			.into_iter()
+			.collect());
+	}
+
+	let base = components[..literal].join("/");
+	let root = if base.is_empty() { "." } else { &base };
+	let mut prefix = base.clone();
+	if !prefix.is_empty() && !prefix.ends_with('/') {
+		prefix.push('/');
+	}
+	let mut paths = Vec::new();
+	let mut dirs = vec![(PathBuf::from(root), prefix)];
+	while let Some((dir, prefix)) = dirs.pop() {
+		for entry in std::fs::read_dir(dir)? {
+			let entry = entry?;
+			let relative = format!("{prefix}{}", entry.file_name().to_string_lossy());
+			if entry.file_type()?.is_dir() {
+				dirs.push((entry.path(), relative + "/"));
+			} else if matches(pattern, &relative) {
+				paths.push(entry.path());
+			}
+		}
+	}
+	paths.sort();
+	Ok(paths)
+}
+
+/// Implementation of [`matches`] on slices of characters
+fn matches_chars(pattern: &[char], path: &[char]) -> bool {
+	match pattern {
+		[] => path.is_empty(),
+		['*', '*', rest @ ..] => {
+			rest.strip_prefix(&['/'])
+				.is_some_and(|rest| matches_chars(rest, path))
+				|| (0..=path.len()).any(|start| matches_chars(rest, &path[start..]))
+		}
+		['*', rest @ ..] => (0..=path.len())
+			.take_while(|&start| !path[..start].contains(&'/'))
+			.any(|start| matches_chars(rest, &path[start..])),
+		['?', rest @ ..] => {
+			path.first().is_some_and(|&c| c != '/') && matches_chars(rest, &path[1..])
+		}
+		[c, rest @ ..] => path.first() == Some(c) && matches_chars(rest, &path[1..]),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn literal() {
+		assert!(matches("src/lib.rs", "src/lib.rs"));
+		assert!(!matches("src/lib.rs", "src/main.rs"));
+	}
+
+	#[test]
+	fn star() {
+		assert!(matches("src/*.rs", "src/lib.rs"));
+		assert!(!matches("src/*.rs", "src/bin/main.rs"));
+	}
+
+	#[test]
+	fn double_star() {
+		assert!(matches("src/**/*.rs", "src/lib.rs"));
+		assert!(matches("src/**/*.rs", "src/bin/tool/main.rs"));
+		assert!(matches("**", "src/lib.rs"));
+		assert!(!matches("src/**/*.rs", "tests/it.rs"));
+	}
+
+	#[test]
+	fn question_mark() {
+		assert!(matches("src/?.rs", "src/a.rs"));
+		assert!(!matches("src/?.rs", "src/ab.rs"));
+	}
+}
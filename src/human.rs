@@ -0,0 +1,61 @@
+//! Provides structures to display annotations in a terminal
+
+use crate::github::Annotation;
+use std::fmt::{self, Display, Formatter};
+
+/// An annotation displayed in a human-readable form, in the style of compilers
+/// (e.g. `src/lib.rs:1:5: warning: unused variable`)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Human<'a, 's>(pub(crate) &'a Annotation<'s>);
+impl<'a, 's> Display for Human<'a, 's> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let annotation = self.0;
+
+		if annotation.has_location() {
+			write!(f, "{}:{}:", annotation.file, annotation.line)?;
+			if let Some(col) = annotation.col {
+				write!(f, "{col}:")?;
+			}
+			f.write_str(" ")?;
+		}
+		write!(f, "{}: ", annotation.kind.name())?;
+		if let Some(title) = &annotation.title {
+			writeln!(f, "{title}")?;
+		}
+		f.write_str(annotation.message.trim())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::github::AnnotationKind;
+	use std::borrow::Cow;
+
+	#[test]
+	fn human() {
+		let mut annotation = Annotation {
+			kind: AnnotationKind::Warning,
+			file: Cow::Borrowed("src/lib.rs"),
+			line: 1,
+			end_line: Some(1),
+			col: Some(5),
+			end_column: Some(6),
+			title: None,
+			message: Cow::Borrowed("unused variable: `x`\n"),
+			package: None,
+			code: None,
+		};
+		assert_eq!(
+			Human(&annotation).to_string(),
+			"src/lib.rs:1:5: warning: unused variable: `x`"
+		);
+
+		annotation.file = Cow::Borrowed("");
+		annotation.title = Some(Cow::Borrowed("unused variable"));
+		assert_eq!(
+			Human(&annotation).to_string(),
+			"warning: unused variable\nunused variable: `x`"
+		);
+	}
+}
@@ -0,0 +1,99 @@
+//! Provides structures to suppress known issues
+
+use crate::glob;
+use std::fs;
+
+/// List of known issues, read from an ignore file
+///
+/// Each non-empty line of the file is an entry of the form `FILE[:LINE[:CODE]]`, where:
+/// - `FILE` is a glob pattern matching the file of the issue
+/// - `LINE` is the line of the issue (`*` or omitted for any line)
+/// - `CODE` is the code of the issue (e.g. `E0308` or `clippy::needless_return`, omitted for any code)
+///
+/// Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct IgnoreList(Vec<IgnoreEntry>);
+impl IgnoreList {
+	/// Reads and parses the ignore file at `path`
+	///
+	/// # Errors
+	/// Returns an error if the file cannot be read or contains an invalid entry.
+	pub(crate) fn read(path: &str) -> Result<Self, String> {
+		Self::parse(&fs::read_to_string(path).map_err(|err| err.to_string())?)
+	}
+
+	/// Parses the content of an ignore file
+	///
+	/// # Errors
+	/// Returns an error if `content` contains an invalid entry.
+	pub(crate) fn parse(content: &str) -> Result<Self, String> {
+		content
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.map(|line| {
+				let mut parts = line.splitn(3, ':');
+				let file = parts.next().unwrap_or_default().to_owned();
+				let line = match parts.next() {
+					None | Some("" | "*") => None,
+					Some(number) => Some(
+						number
+							.parse()
+							.map_err(|err| format!("invalid line in `{line}`: {err}"))?,
+					),
+				};
+				let code = parts
+					.next()
+					.filter(|code| !code.is_empty())
+					.map(str::to_owned);
+				Ok(IgnoreEntry { file, line, code })
+			})
+			.collect::<Result<_, _>>()
+			.map(Self)
+	}
+
+	/// Returns `true` if an issue is listed
+	pub(crate) fn matches(&self, file: &str, line: usize, code: Option<&str>) -> bool {
+		self.0.iter().any(|entry| {
+			glob::matches(&entry.file, file)
+				&& entry.line.is_none_or(|entry_line| entry_line == line)
+				&& entry
+					.code
+					.as_deref()
+					.is_none_or(|entry_code| code == Some(entry_code))
+		})
+	}
+}
+
+/// Entry of an [`IgnoreList`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IgnoreEntry {
+	/// Glob pattern matching the file of the issue
+	file: String,
+	/// Line of the issue
+	line: Option<usize>,
+	/// Code of the issue
+	code: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse() {
+		let list = IgnoreList::parse(
+			"# Known issues\n\nsrc/lib.rs:3:clippy::needless_return\nsrc/**/*.rs:*:E0308\n",
+		)
+		.unwrap();
+		assert!(list.matches("src/lib.rs", 3, Some("clippy::needless_return")));
+		assert!(!list.matches("src/lib.rs", 4, Some("clippy::needless_return")));
+		assert!(list.matches("src/bin/main.rs", 10, Some("E0308")));
+		assert!(!list.matches("src/bin/main.rs", 10, None));
+	}
+
+	#[test]
+	fn parse_invalid_line() {
+		assert!(IgnoreList::parse("src/lib.rs:three").is_err());
+	}
+}
@@ -8,13 +8,11 @@
 	unused,
 	unused_crate_dependencies,
 	unused_lifetimes,
-	unused_tuple_struct_fields,
 	clippy::dbg_macro,
 	clippy::empty_structs_with_brackets,
 	clippy::enum_glob_use,
 	clippy::float_cmp_const,
 	clippy::format_push_string,
-	clippy::match_on_vec_items,
 	clippy::missing_docs_in_private_items,
 	clippy::mod_module_files,
 	clippy::option_option,
@@ -25,7 +23,6 @@
 	noop_method_call,
 	meta_variable_misuse,
 	// Pedantic
-	unused_qualifications,
 	clippy::doc_link_with_quotes,
 	clippy::doc_markdown,
 	clippy::filter_map_next,
@@ -50,8 +47,6 @@
 	clippy::suboptimal_flops,
 )]
 #![deny(
-	// Correctness
-	pointer_structural_match,
 	// Restriction
 	keyword_idents,
 	non_ascii_idents,
@@ -64,51 +59,104 @@
 )]
 #![forbid(unsafe_code)]
 
-use clap::{Args, Parser, Subcommand, ValueHint};
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
 use std::{
-	collections::{BTreeSet, HashMap},
+	borrow::Cow,
+	cmp::Reverse,
+	collections::{BTreeMap, BTreeSet, HashMap},
+	error::Error,
 	ffi::OsString,
-	fmt::Write as FmtWrite,
-	fs::File,
-	io::{self, BufRead, Cursor, Write as IoWrite},
-	process::{Command, ExitCode, Output, Stdio},
+	fmt::{self, Display, Formatter, Write as FmtWrite},
+	fs::{self, File, OpenOptions},
+	io::{self, BufRead, BufReader, Read, Write as IoWrite},
+	path::{Path, PathBuf},
+	process::{Child, ChildStdout, Command, ExitCode, ExitStatus, Stdio},
+	thread::{self, JoinHandle},
+	time::{Duration, Instant, SystemTime},
 };
 
+mod azure;
+mod bitbucket;
 mod cargo;
 mod github;
+mod glob;
+mod human;
+mod ignore;
+mod sarif;
 
-use cargo::{CargoMessage, Diagnostic, DiagnosticLevel};
-use github::{Annotation, AnnotationKind};
+use azure::LogIssue;
+use bitbucket::CodeInsightsReport;
+use cargo::{
+	is_error_code, package_dir, package_name, rendered_location, ArtifactMessage, CargoMessage,
+	CompilerMessage, DiagnosticLevel, JsonObjects, Metadata, TestEvent, TestMessage,
+};
+use github::{Annotation, AnnotationError, AnnotationKind, MAX_COMMAND_LEN};
+use human::Human;
+use ignore::IgnoreList;
+use sarif::{SarifLog, SarifResult};
 
 fn main() -> ExitCode {
 	let cli = Cli::parse_from(std::env::args_os().filter(|arg| arg != "ghannotate"));
 
-	let annotation_threshold = if cli.allow_warnings {
-		AnnotationKind::Error
+	if let Some(CliCommand::Doctor) = cli.command {
+		let current_dir = std::env::current_dir().expect("Current directory is inaccessible");
+		let healthy = cli
+			.doctor(
+				|var| std::env::var_os(var),
+				&current_dir,
+				&mut io::stdout().lock(),
+			)
+			.unwrap();
+		return if healthy {
+			ExitCode::SUCCESS
+		} else {
+			ExitCode::FAILURE
+		};
+	}
+
+	let package_names = if cli.cargo_metadata {
+		cli.invoke_cargo_metadata().unwrap_or_else(|err| {
+			eprintln!("warning: the crates are not labelled with their names: {err}");
+			HashMap::new()
+		})
 	} else {
-		AnnotationKind::Warning
+		HashMap::new()
 	};
-	let mut max_annotation = AnnotationKind::Notice;
-
-	let cargo = cli.invoke_cargo().expect("Cargo invocation failed");
-	let mut summaries = Vec::new();
-	let mut annotations = BTreeSet::new();
 	let mut stdout = io::stdout().lock();
-	for line in Cursor::new(cargo.stdout).lines() {
-		if let Ok(message) = serde_json::from_str::<CargoMessage>(&line.unwrap()) {
-			let summary = Summary::from(&message);
-			if let Ok(annotation) = Annotation::try_from(message) {
-				if annotations.insert(annotation.to_owned()) {
-					writeln!(stdout, "{annotation}").unwrap();
-					max_annotation = max_annotation.max(annotation.kind);
-					summaries.push(summary);
-				}
+	if cli.watch {
+		if std::env::var_os("GITHUB_ACTIONS").is_some() {
+			eprintln!("warning: `--watch` is ignored in GitHub Actions");
+		} else {
+			let mut cli = cli;
+			if cli.output_format == OutputFormat::Github {
+				cli.output_format = OutputFormat::Human;
 			}
+			let mut watcher =
+				SourceWatcher::new(PathBuf::from(".")).expect("Source files could not be listed");
+			cli.watch(
+				|| watcher.wait().map(|()| true),
+				summary_path().as_deref(),
+				&package_names,
+				&mut stdout,
+			)
+			.unwrap();
+			return ExitCode::SUCCESS;
+		}
+	}
+	let (report, cargo_success, timed_out) = cli.run(&mut stdout).expect("Cargo invocation failed");
+	if timed_out {
+		cli.emit_timeout_error(&mut stdout).unwrap();
+	}
+	cli.emit_success(&report, &mut stdout).unwrap();
+	let failure = cli.should_fail(&report, cargo_success, timed_out);
+	cli.write_summaries(&report, &package_names).unwrap();
+	if cli.write_github_output {
+		if let Some(path) = std::env::var_os("GITHUB_OUTPUT") {
+			write_github_output(Path::new(&path), &report, failure).unwrap();
 		}
 	}
-	write_summaries(summaries).unwrap();
 
-	if max_annotation >= annotation_threshold {
+	if failure {
 		ExitCode::FAILURE
 	} else {
 		ExitCode::SUCCESS
@@ -120,7 +168,9 @@ fn main() -> ExitCode {
 #[command(author, version, about, long_about = None)]
 #[command(override_usage = "cargo ghannotate check [OPTIONS] [ARGS]...\n       \
 	cargo ghannotate clippy [OPTIONS] [ARGS]...\n       \
-	cargo ghannotate build [OPTIONS] [ARGS]...")]
+	cargo ghannotate build [OPTIONS] [ARGS]...\n       \
+	cargo ghannotate test [OPTIONS] [ARGS]...\n       \
+	cargo ghannotate multi <COMMANDS>... [-- <ARGS>...]")]
 struct Cli {
 	/// Path to the `cargo` executable
 	#[arg(long, env = "CARGO", value_name = "PATH", value_hint = ValueHint::ExecutablePath)]
@@ -128,180 +178,4311 @@ struct Cli {
 	/// Should warnings be raised, they would not cause the job to fail
 	#[arg(long)]
 	allow_warnings: bool,
+	/// Do not fail the job when Cargo fails without reporting any error (DANGEROUS: the failure is silenced)
+	#[arg(long)]
+	exit_zero_on_no_diagnostics_even_if_cargo_fails: bool,
+	/// Print notes about the processing of diagnostics
+	#[arg(short, long)]
+	verbose: bool,
+	/// Print a note for each annotated file that does not exist in the workspace
+	///
+	/// GitHub only displays the annotations of existing files inline, which helps to debug the rewriting of paths.
+	#[arg(long, requires = "verbose")]
+	annotation_file_exists_check: bool,
+	/// Format of the emitted annotations
+	#[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Github)]
+	output_format: OutputFormat,
+	/// Emit the annotations in this order once all of them are collected, rather than as they are parsed
+	#[arg(long, value_enum, value_name = "ORDER")]
+	emit_order: Option<EmitOrder>,
+	/// Collapse the annotations of a same code and kind at most this number of lines apart into a single annotation
+	#[arg(long, alias = "annotation-dedup-window", value_name = "LINES")]
+	dedup_window: Option<usize>,
+	/// List the lints and error codes of the results, with links to their documentation, in the SARIF log
+	#[arg(long)]
+	emit_sarif_rules: bool,
+	/// Label diagnostics with the names of their crates, as reported by `cargo metadata`
+	#[arg(long)]
+	cargo_metadata: bool,
+	/// Read Cargo's JSON messages from this file (`-` for stdin) instead of invoking Cargo
+	#[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+	input: Option<PathBuf>,
+	/// Read and merge Cargo's JSON messages from all the files matching this glob pattern (e.g. `artifacts/**/*.json`) instead of invoking Cargo
+	///
+	/// The annotations duplicated across the files are emitted once.
+	#[arg(long, value_name = "PATTERN", conflicts_with = "input")]
+	input_glob: Option<String>,
+	/// Kill Cargo if it runs longer than this duration (e.g. `30m`)
+	#[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+	cargo_timeout: Option<Duration>,
+	/// Capture Cargo's standard error, to show its tail in the summary if Cargo fails without errors
+	///
+	/// The captured output is still forwarded to the standard error.
+	#[arg(long)]
+	capture_stderr: bool,
+	/// Run Cargo again whenever a source file changes, for local development
+	///
+	/// The annotations are then emitted in the human-readable format, unless another output format is passed.
+	/// It is ignored in GitHub Actions.
+	#[arg(long, conflicts_with_all = ["input", "input_glob"])]
+	watch: bool,
+	/// Fail the job only if there are more errors than this number
+	#[arg(long, value_name = "N")]
+	max_errors: Option<usize>,
+	/// Fail the job only if there are more warnings than this number
+	#[arg(long, value_name = "N")]
+	max_warnings: Option<usize>,
+	/// Emit errors with this kind instead, so that they do not fail the job
+	#[arg(
+		long,
+		alias = "annotation-kind-override",
+		value_enum,
+		value_name = "KIND"
+	)]
+	downgrade_errors_to: Option<DowngradedKind>,
+	/// Emit the diagnostics of a level with another kind (e.g. `warning=error`; repeatable)
+	///
+	/// The levels are `error`, `warning`, `note`, `help`, `failure-note` and `ice`,
+	/// and the kinds are `error`, `warning` and `notice`.
+	/// A mapping of the `error` level takes precedence over `--downgrade-errors-to`.
+	#[arg(long, value_name = "LEVEL=KIND", value_parser = LevelMapping::parse)]
+	map_level: Vec<LevelMapping>,
+	/// Do not annotate the known issues listed in this file
+	#[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, value_parser = IgnoreList::read)]
+	ignore_file: Option<IgnoreList>,
+	/// Spell the annotated file paths like the files on disk (or else lowercase them), for case-insensitive filesystems
+	#[arg(long)]
+	normalize_path_case: bool,
+	/// Only annotate the first line of the spans longer than this number of lines [default: 10]
+	#[arg(
+		long,
+		value_name = "LINES",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "10"
+	)]
+	annotate_first_line_only: Option<usize>,
+	/// Only annotate the first line of the spans longer than this number of lines, and skip the spans of a whole file
+	#[arg(long, value_name = "LINES")]
+	max_span_lines: Option<usize>,
+	/// Options of the conversion of Cargo's messages into annotations
+	#[command(flatten)]
+	options: Options,
+	/// Only annotate files modified within this duration (e.g. `10m`)
+	#[arg(long, alias = "since-duration", value_name = "DURATION", value_parser = parse_duration)]
+	changed_within: Option<Duration>,
+	/// Truncate the messages in the summary to this number of characters
+	///
+	/// Only the first line of the messages is kept in any case.
+	#[arg(long, value_name = "N")]
+	summary_max_message_chars: Option<usize>,
+	/// Emit a notice if no diagnostic was annotated, as a positive signal that the step ran
+	#[arg(long)]
+	emit_empty_success_annotation: bool,
+	/// Prepend this tag, in brackets, to the titles of the annotations and the messages of the summary
+	#[arg(long, value_name = "TAG")]
+	annotation_prefix: Option<String>,
+	/// Write the diagnostics of the summary in one table per kind, errors first
+	///
+	/// It is equivalent to `--summary-layout=by-kind`.
+	#[arg(long, conflicts_with = "summary_layout")]
+	summary_split_by_kind: bool,
+	/// Grouping of the diagnostics of the summary
+	#[arg(long, value_enum, value_name = "LAYOUT", default_value_t = SummaryLayout::Flat)]
+	summary_layout: SummaryLayout,
+	/// Omit the Location column of the diagnostics tables of the summary
+	#[arg(long)]
+	summary_no_location_column: bool,
+	/// Append a hint to the messages of the summary for the most common resolution errors (e.g. `E0432`)
+	#[arg(long)]
+	summary_hints: bool,
+	/// List every built crate of the workspace in the summary, with its numbers of diagnostics (even if none)
+	#[arg(long)]
+	summary_include_passing_crates: bool,
+	/// Format of the locations in the summary
+	#[arg(long, value_enum, value_name = "FORMAT", default_value_t = LocationFormat::Default)]
+	location_format: LocationFormat,
+	/// Presentation of the totals of the summary
+	#[arg(long, value_enum, value_name = "THEME", default_value_t = SummaryTheme::Plain)]
+	summary_theme: SummaryTheme,
+	/// Template of the totals line of the summary
+	///
+	/// The `{errors}`, `{warnings}`, `{notices}`, `{total}` and `{files}` placeholders are replaced by the numbers of annotated diagnostics and files.
+	/// In the plain theme, it defaults to the `TOTAL` line; in the rich theme, it is written after the chips.
+	#[arg(long, value_name = "TEMPLATE")]
+	summary_footer_template: Option<String>,
+	/// List the rendered diagnostics in the summary, reformatted as Markdown
+	#[arg(long)]
+	reformat_message: bool,
+	/// Write the counts of annotations and whether the job fails as outputs of the step (see `GITHUB_OUTPUT`)
+	#[arg(long)]
+	write_github_output: bool,
+	/// Append to the summary file while holding a lock on it, for concurrent invocations sharing it
+	#[arg(long)]
+	concurrency_safe_summary: bool,
+	/// Write a partial summary at most this often (e.g. `10s`), so that it survives an interrupted run
+	#[arg(
+		long,
+		value_name = "DURATION",
+		value_parser = parse_duration,
+		conflicts_with = "concurrency_safe_summary"
+	)]
+	summary_flush_interval: Option<Duration>,
 	/// Cargo subcommand
 	#[command(subcommand)]
-	command: CliCommand,
+	command: Option<CliCommand>,
 }
 impl Cli {
-	/// Invokes Cargo with the passed arguments and returns its output
-	#[inline]
-	fn invoke_cargo(&self) -> io::Result<Output> {
+	/// Opens the input of the JSON messages, invoking Cargo if needed
+	///
+	/// Annotates from the input, or from the output of the Cargo invocations
+	///
+	/// The workflow commands are written to `out`.
+	/// Returns the report, whether Cargo succeeded, and whether it timed out.
+	///
+	/// # Errors
+	/// Returns any error that occurred while invoking Cargo or writing to `out`.
+	fn run(&self, mut out: impl IoWrite) -> io::Result<(Report, bool, bool)> {
+		if let Some(pattern) = &self.input_glob {
+			let paths = glob::expand(pattern)?;
+			if paths.is_empty() {
+				eprintln!("warning: no file matches `{pattern}`");
+			}
+			return Ok((self.process_files(&paths, out)?, true, false));
+		}
+		let command = match (&self.input, &self.command) {
+			(None, Some(CliCommand::Multi(multi))) => return self.process_multi(multi, out),
+			(None, Some(command)) => command,
+			_ => return Ok((self.process(self.input(), out)?, true, false)),
+		};
+		let mut cargo = self.invoke_cargo(command)?;
+		let mut report = self.new_report();
+		self.process_into(&mut cargo.stdout, &mut report, &mut out)?;
+		let output = cargo.wait()?;
+		let cargo_success = output.status.is_some_and(|status| status.success());
+		if !cargo_success {
+			report.cargo_stderr = output.stderr;
+		}
+		self.finish(&report, &mut out)?;
+		Ok((report, cargo_success, output.status.is_none()))
+	}
+
+	/// Runs Cargo and annotates from its output, then again each time `wait_for_change` returns `true` (see `--watch`)
+	///
+	/// The terminal is cleared before each run, and the summary file at `summary_path` is rewritten after each run.
+	/// Returns the number of runs.
+	///
+	/// # Errors
+	/// Returns any error that occurred while invoking Cargo, writing to `out` or waiting for a change.
+	fn watch(
+		&self,
+		mut wait_for_change: impl FnMut() -> io::Result<bool>,
+		summary_path: Option<&Path>,
+		package_names: &HashMap<String, String>,
+		mut out: impl IoWrite,
+	) -> io::Result<usize> {
+		let mut runs = 0;
+		loop {
+			// Clear the terminal and move the cursor to its top-left corner
+			write!(out, "\x1b[2J\x1b[H")?;
+			let (report, cargo_success, timed_out) = self.run(&mut out)?;
+			runs += 1;
+			if timed_out {
+				self.emit_timeout_error(&mut out)?;
+			}
+			self.emit_success(&report, &mut out)?;
+			out.flush()?;
+			if let Some(path) = summary_path {
+				self.write_summary_file(path, &report, package_names)?;
+			}
+
+			let outcome = if self.should_fail(&report, cargo_success, timed_out) {
+				"Failed"
+			} else {
+				"Passed"
+			};
+			eprintln!("{outcome}, watching for changes...");
+			if !wait_for_change()? {
+				return Ok(runs);
+			}
+		}
+	}
+
+	/// Opens the input file (see `--input`)
+	fn input(&self) -> Box<dyn BufRead> {
+		match &self.input {
+			Some(path) if path.as_os_str() == "-" => Box::new(io::stdin().lock()),
+			Some(path) => match File::open(path) {
+				Ok(file) => Box::new(BufReader::new(file)),
+				Err(err) => Self::command()
+					.error(
+						ErrorKind::Io,
+						format!("could not open `{}`: {err}", path.display()),
+					)
+					.exit(),
+			},
+			None => Self::command()
+				.error(
+					ErrorKind::MissingSubcommand,
+					"a Cargo subcommand, `--input` or `--input-glob` is required",
+				)
+				.exit(),
+		}
+	}
+
+	/// Invokes Cargo with the passed arguments
+	///
+	/// If Cargo runs longer than `--cargo-timeout`, it is killed along with its process group,
+	/// and no exit status is returned.
+	fn invoke_cargo(&self, command: &CliCommand) -> io::Result<CargoProcess> {
 		#[allow(clippy::enum_glob_use)]
 		use CliCommand::*;
 
-		Command::new(&self.cargo)
-			.arg(match self.command {
-				Check(_) => "check",
-				Clippy(_) => "clippy",
-				Build(_) => "build",
-			})
-			.args(self.command.as_ref().as_ref())
+		let subcommand = match command {
+			Check(_) => CargoSubcommand::Check,
+			Clippy(_) => CargoSubcommand::Clippy,
+			Build(_) => CargoSubcommand::Build,
+			Test(_) => CargoSubcommand::Test,
+			Multi(_) | Doctor => {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"not a single Cargo subcommand",
+				))
+			}
+		};
+		self.invoke_cargo_subcommand(subcommand, command.args())
+	}
+
+	/// Invokes `cargo <subcommand>` with `args`
+	///
+	/// The JSON messages are to be read from the standard output of the returned process while Cargo runs.
+	/// The standard error is inherited, unless `--capture-stderr` tees it into the output.
+	///
+	/// # Errors
+	/// Returns any error that occurred while invoking Cargo.
+	fn invoke_cargo_subcommand(
+		&self,
+		subcommand: CargoSubcommand,
+		args: &[OsString],
+	) -> io::Result<CargoProcess> {
+		for warning in cargo_args_warnings(args) {
+			eprintln!("warning: {warning}");
+		}
+		let mut cargo = Command::new(&self.cargo);
+		cargo
+			.arg(subcommand.name())
 			.arg("--message-format=json")
+			.args(args)
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(if self.capture_stderr {
+				Stdio::piped()
+			} else {
+				Stdio::inherit()
+			});
+		#[cfg(unix)]
+		if self.cargo_timeout.is_some() {
+			std::os::unix::process::CommandExt::process_group(&mut cargo, 0);
+		}
+		let mut child = cargo.spawn()?;
+
+		let stdout = child.stdout.take().expect("Cargo's stdout is piped");
+		let stderr_reader = child.stderr.take().map(|mut stderr| {
+			thread::spawn(move || -> io::Result<Vec<u8>> {
+				let mut buffer = Vec::new();
+				let mut chunk = [0; 8192];
+				loop {
+					let len = stderr.read(&mut chunk)?;
+					if len == 0 {
+						return Ok(buffer);
+					}
+					io::stderr().write_all(&chunk[..len])?;
+					buffer.extend_from_slice(&chunk[..len]);
+				}
+			})
+		});
+		let timeout = self.cargo_timeout;
+		let waiter = thread::spawn(move || match timeout {
+			Some(timeout) => wait_timeout(&mut child, timeout),
+			None => child.wait().map(Some),
+		});
+		Ok(CargoProcess {
+			stdout: BufReader::new(stdout),
+			waiter,
+			stderr_reader,
+		})
+	}
+
+	/// Invokes `cargo metadata` and returns the names of the workspace packages by their IDs
+	///
+	/// # Errors
+	/// Returns any error that occurred while invoking Cargo or parsing its output, or if Cargo failed.
+	fn invoke_cargo_metadata(&self) -> io::Result<HashMap<String, String>> {
+		let output = Command::new(&self.cargo)
+			.args(["metadata", "--no-deps", "--format-version", "1"])
 			.stdin(Stdio::null())
 			.stderr(Stdio::inherit())
-			.output()
+			.output()?;
+		if !output.status.success() {
+			return Err(io::Error::other(format!(
+				"`cargo metadata` failed ({})",
+				output.status
+			)));
+		}
+		package_names(&output.stdout).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
 	}
-}
 
-/// Cargo subcommand
-#[derive(Debug, Clone, Subcommand)]
-enum CliCommand {
-	/// Runs `cargo check` and annotates from its output
-	Check(CliCommandArgs),
-	/// Runs `cargo clippy` and annotates from its output
-	Clippy(CliCommandArgs),
-	/// Runs `cargo build` and annotates from its output
-	Build(CliCommandArgs),
-}
-impl AsRef<CliCommandArgs> for CliCommand {
-	#[inline]
-	fn as_ref(&self) -> &CliCommandArgs {
-		match self {
-			Self::Check(args) | Self::Clippy(args) | Self::Build(args) => args,
+	/// Annotates from the JSON messages outputted by Cargo
+	///
+	/// The workflow commands are written to `out`.
+	///
+	/// # Errors
+	/// Returns any error that occurred while reading `input` or writing to `out`.
+	fn process(&self, input: impl BufRead, mut out: impl IoWrite) -> io::Result<Report> {
+		let mut report = self.new_report();
+		self.process_into(input, &mut report, &mut out)?;
+		self.finish(&report, &mut out)?;
+		Ok(report)
+	}
+
+	/// Creates an empty report, flushed to the summary file according to `--summary-flush-interval`
+	fn new_report(&self) -> Report {
+		let mut report = Report::new();
+		report.summary_flush = self.summary_flush_interval.and_then(|interval| {
+			Some(SummaryFlush {
+				path: summary_path()?,
+				interval,
+				flushed_at: Instant::now(),
+			})
+		});
+		report
+	}
+
+	/// Invokes each subcommand of `multi` in turn, and annotates from their merged outputs
+	///
+	/// The workflow commands are written to `out`.
+	/// Returns the report, whether all invocations succeeded, and whether any of them timed out.
+	///
+	/// # Errors
+	/// Returns any error that occurred while invoking Cargo or writing to `out`.
+	fn process_multi(
+		&self,
+		multi: &MultiArgs,
+		mut out: impl IoWrite,
+	) -> io::Result<(Report, bool, bool)> {
+		let mut report = self.new_report();
+		let mut success = true;
+		let mut timed_out = false;
+		for &subcommand in &multi.commands {
+			let mut cargo = self.invoke_cargo_subcommand(subcommand, &multi.args)?;
+			report.subcommand = Some(subcommand);
+			self.process_into(&mut cargo.stdout, &mut report, &mut out)?;
+			let output = cargo.wait()?;
+			let subcommand_success = output.status.is_some_and(|status| status.success());
+			success &= subcommand_success;
+			timed_out |= output.status.is_none();
+			if !subcommand_success {
+				report.cargo_stderr.extend(output.stderr);
+			}
 		}
+		report.subcommand = None;
+		self.finish(&report, &mut out)?;
+		Ok((report, success, timed_out))
 	}
-}
 
-/// Arguments to be passed down to Cargo
-#[derive(Debug, Clone, Args)]
-#[repr(transparent)]
-struct CliCommandArgs {
-	/// Arguments to be passed down to Cargo
-	#[arg(
-		num_args = 0..,
-		trailing_var_arg = true,
-		allow_hyphen_values = true,
-	)]
-	args: Vec<OsString>,
-}
-impl AsRef<[OsString]> for CliCommandArgs {
-	#[inline]
-	fn as_ref(&self) -> &[OsString] {
-		self.args.as_ref()
+	/// Annotates from the merged JSON messages of the files at `paths` (see `--input-glob`)
+	///
+	/// The workflow commands are written to `out`.
+	/// A file that cannot be read or parsed is reported and skipped, without discarding the messages read before the error.
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn process_files(&self, paths: &[PathBuf], mut out: impl IoWrite) -> io::Result<Report> {
+		let mut report = self.new_report();
+		for path in paths {
+			let result = File::open(path)
+				.and_then(|file| self.process_into(BufReader::new(file), &mut report, &mut out));
+			if let Err(err) = result {
+				eprintln!("warning: skipping the rest of {} ({err})", path.display());
+			}
+		}
+		self.finish(&report, &mut out)?;
+		Ok(report)
 	}
-}
 
-/// Summary of [`CargoMessage`]
-#[derive(Debug, Clone)]
-enum Summary {
-	/// Summary of [`Diagnostic`]
-	Diagnostic {
-		/// [`Diagnostic.level`](Diagnostic#structfield.level)
-		level: DiagnosticLevel,
-		/// [`Diagnostic.message`](Diagnostic#structfield.message)
-		message: String,
-		/// Location of the diagnostic (primary [span](cargo::DiagnosticSpan))
-		location: Option<(String, usize)>,
-	},
-}
-impl<'c> From<&'c Diagnostic<'c>> for Summary {
+	/// Annotates from the JSON messages outputted by Cargo into `report`
+	///
+	/// # Errors
+	/// Returns any error that occurred while reading `input` or writing to `out`.
+	fn process_into(
+		&self,
+		input: impl BufRead,
+		report: &mut Report,
+		out: &mut impl IoWrite,
+	) -> io::Result<()> {
+		for message in annotations(input, &self.options) {
+			match message {
+				Ok(Message::Annotation(annotation, summary)) => {
+					self.emit(annotation, summary, report, out)?;
+				}
+				Ok(Message::Artifact(package_id)) => {
+					if self.summary_include_passing_crates {
+						report.built_packages.insert(package_id);
+					}
+				}
+				Err(ProcessError::Invalid(err)) => {
+					if self.verbose {
+						eprintln!("note: skipping invalid annotation: {err}");
+					}
+				}
+				Err(ProcessError::Io(err)) => return Err(err),
+			}
+			self.flush_summary(report)?;
+		}
+		Ok(())
+	}
+
+	/// Writes what is output once all annotations are collected
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn finish(&self, report: &Report, out: &mut impl IoWrite) -> io::Result<()> {
+		if self.defers_annotations() {
+			for annotation in self.deferred_annotations(report) {
+				self.write_annotation(&annotation, out)?;
+			}
+		}
+		if self.output_format == OutputFormat::Bitbucket {
+			serde_json::to_writer(
+				&mut *out,
+				&CodeInsightsReport::from_iter(&self.deferred_annotations(report)),
+			)?;
+			writeln!(out)?;
+		}
+		if self.output_format == OutputFormat::Sarif {
+			// The results are collapsed like the annotations (see `--dedup-window`)
+			let collapsed_results;
+			let results = if self.dedup_window.is_some() {
+				collapsed_results = self
+					.deferred_annotations(report)
+					.iter()
+					.map(|annotation| SarifResult::new(annotation, annotation.code.as_deref()))
+					.collect::<Vec<_>>();
+				&collapsed_results
+			} else {
+				&report.sarif_results
+			};
+			serde_json::to_writer(&mut *out, &SarifLog::new(results, self.emit_sarif_rules))?;
+			writeln!(out)?;
+		}
+		Ok(())
+	}
+
+	/// Emits `annotation` to `out` and records it in `report`, unless it is a duplicate
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn emit(
+		&self,
+		mut annotation: Annotation,
+		mut summary: Summary,
+		report: &mut Report,
+		out: &mut impl IoWrite,
+	) -> io::Result<()> {
+		if let Some(duration) = self.changed_within.filter(|_| annotation.has_location()) {
+			if !modified_within(annotation.file.as_ref(), duration) {
+				if self.verbose {
+					eprintln!(
+						"note: skipping annotation of a file not modified recently: {annotation:?}"
+					);
+				}
+				return Ok(());
+			}
+		}
+		if let (Some(ignore_list), Summary::Diagnostic { code, .. }) = (&self.ignore_file, &summary)
+		{
+			if ignore_list.matches(&annotation.file, annotation.line, code.as_deref()) {
+				if annotation.kind == AnnotationKind::Error {
+					report.hidden_errors += 1;
+				}
+				report.ignored.push(summary);
+				return Ok(());
+			}
+		}
+		if self.normalize_path_case {
+			let file = workspace_root()
+				.and_then(|root| real_path_case(&annotation.file, &root))
+				.unwrap_or_else(|| annotation.file.to_lowercase());
+			annotation.file = Cow::Owned(file);
+		}
+		if let (Some(subcommand), Summary::Diagnostic { message, .. }) =
+			(report.subcommand, &mut summary)
+		{
+			*message = format!("`{}`: {message}", subcommand.name());
+		}
+		if let Some(prefix) = &self.annotation_prefix {
+			let title = annotation
+				.title
+				.as_deref()
+				.unwrap_or_else(|| annotation.message.trim().lines().next().unwrap_or_default());
+			annotation.title = Some(Cow::Owned(format!("[{prefix}] {title}")));
+			match &mut summary {
+				Summary::Diagnostic { message, .. } => *message = format!("[{prefix}] {message}"),
+				Summary::Test { name, .. } => *name = format!("[{prefix}] {name}"),
+			}
+		}
+		if let (Some(max_lines), Some(end_line)) = (self.max_span_lines, annotation.end_line) {
+			if end_line.saturating_sub(annotation.line) >= max_lines {
+				if annotation.line == 1
+					&& line_count(annotation.file.as_ref()).is_some_and(|count| end_line >= count)
+				{
+					// A span of the whole file is useless in the diff view, so it is only listed in the summary
+					if self.verbose {
+						eprintln!("note: skipping annotation of a whole file: {annotation:?}");
+					}
+					if annotation.kind == AnnotationKind::Error {
+						report.hidden_errors += 1;
+					}
+					report.summaries.push(summary);
+					return Ok(());
+				} else {
+					annotation.end_line = Some(annotation.line);
+				}
+			}
+		}
+		if let (Some(max_lines), Some(end_line)) =
+			(self.annotate_first_line_only, annotation.end_line)
+		{
+			if end_line.saturating_sub(annotation.line) > max_lines {
+				annotation.end_line = Some(annotation.line);
+			}
+		}
+		if self.output_format == OutputFormat::Github {
+			// GitHub may drop the commands that are too long
+			annotation.truncate_to_command_len(MAX_COMMAND_LEN);
+		}
+		let original_kind = annotation.kind;
+		let level = match &summary {
+			Summary::Diagnostic { level, .. } => Some(*level),
+			Summary::Test { .. } => None,
+		};
+		if self.verbose && level == Some(DiagnosticLevel::Unknown) {
+			eprintln!(
+				"note: annotating a diagnostic of an unknown level as a notice: {annotation:?}"
+			);
+		}
+		annotation.kind = self.emitted_kind(original_kind, level);
+		if report.annotations.insert(annotation.to_owned()) {
+			if self.annotation_file_exists_check {
+				if let Some(note) =
+					workspace_root().and_then(|root| missing_file_note(&annotation, &root))
+				{
+					eprintln!("note: {note}");
+				}
+			}
+			if original_kind == AnnotationKind::Error && annotation.kind != AnnotationKind::Error {
+				report.hidden_errors += 1;
+			}
+			match self.output_format {
+				// The annotations are written once all of them are collected if they are reordered
+				OutputFormat::Github | OutputFormat::Azure | OutputFormat::Human
+					if self.defers_annotations() => {}
+				OutputFormat::Github | OutputFormat::Azure | OutputFormat::Human => {
+					self.write_annotation(&annotation, out)?;
+				}
+				// The report is written once all annotations are collected
+				OutputFormat::Bitbucket => {}
+				OutputFormat::Sarif => {
+					let code = match &summary {
+						Summary::Diagnostic { code, .. } => code.as_deref(),
+						Summary::Test { .. } => None,
+					};
+					report
+						.sarif_results
+						.push(SarifResult::new(&annotation, code));
+				}
+			}
+			report.max_annotation = report.max_annotation.max(annotation.kind);
+			report.summaries.push(summary);
+		}
+		Ok(())
+	}
+
+	/// Returns `true` if the annotations are written once all of them are collected (see `--emit-order` and `--dedup-window`)
 	#[inline]
-	fn from(message: &'c Diagnostic<'c>) -> Self {
-		Self::Diagnostic {
-			level: message.level,
-			message: message.message.to_owned(),
-			location: message.spans.iter().find_map(|span| {
-				span.is_primary
-					.then(|| (span.file_name.to_owned(), span.line_start))
-			}),
+	const fn defers_annotations(&self) -> bool {
+		self.emit_order.is_some() || self.dedup_window.is_some()
+	}
+
+	/// Returns the annotations of `report` to write once all of them are collected
+	///
+	/// With `--dedup-window`, the annotations of a same code and kind that are close to each other in a file
+	/// are collapsed into a single annotation spanning all of them, with their count in its title.
+	/// They are then ordered according to `--emit-order`.
+	fn deferred_annotations(&self, report: &Report) -> Vec<Annotation<'static>> {
+		let mut annotations: Vec<(Annotation<'static>, usize)> = Vec::new();
+		// Index in `annotations` of the last collapsed annotation of each file, kind and code
+		let mut groups: HashMap<(&str, AnnotationKind, &str), usize> = HashMap::new();
+		for annotation in &report.annotations {
+			if let (Some(window), Some(code)) = (self.dedup_window, annotation.code.as_deref()) {
+				let group = (annotation.file.as_ref(), annotation.kind, code);
+				if let Some(&index) = groups.get(&group) {
+					let (last, count) = &mut annotations[index];
+					let last_line = last.end_line.unwrap_or(last.line);
+					if annotation.line <= last_line + window {
+						last.end_line =
+							Some(last_line.max(annotation.end_line.unwrap_or(annotation.line)));
+						last.col = None;
+						last.end_column = None;
+						*count += 1;
+						continue;
+					}
+				}
+				groups.insert(group, annotations.len());
+			}
+			annotations.push((annotation.clone(), 1));
+		}
+
+		let mut annotations = annotations
+			.into_iter()
+			.map(|(mut annotation, count)| {
+				if count > 1 {
+					let title = annotation.title.as_deref().unwrap_or_else(|| {
+						annotation.message.trim().lines().next().unwrap_or_default()
+					});
+					annotation.title = Some(Cow::Owned(format!("{title} × {count}")));
+				}
+				annotation
+			})
+			.collect::<Vec<_>>();
+		if self.emit_order == Some(EmitOrder::Severity) {
+			// The sort is stable, so the annotations of a kind remain ordered by location
+			annotations.sort_by_key(|annotation| Reverse(annotation.kind));
 		}
+		annotations
 	}
-}
-impl<'c> From<&'c CargoMessage<'c>> for Summary {
+
+	/// Writes `annotation` to `out` as a command of the output format
+	///
+	/// Nothing is written for the formats that output a single report.
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn write_annotation(&self, annotation: &Annotation, out: &mut impl IoWrite) -> io::Result<()> {
+		match self.output_format {
+			OutputFormat::Github => writeln!(out, "{annotation}"),
+			OutputFormat::Azure => writeln!(out, "{}", LogIssue(annotation)),
+			OutputFormat::Human => writeln!(out, "{}", Human(annotation)),
+			OutputFormat::Bitbucket | OutputFormat::Sarif => Ok(()),
+		}
+	}
+
+	/// Returns the kind with which an annotation of `kind` is emitted, according to `--map-level` and `--downgrade-errors-to`
+	///
+	/// `level` is the level of the diagnostic of the annotation, if any (e.g. not for failed tests).
 	#[inline]
-	fn from(message: &'c CargoMessage<'c>) -> Self {
-		match message {
-			CargoMessage::CompilerMessage(message) => Self::from(message),
+	fn emitted_kind(&self, kind: AnnotationKind, level: Option<DiagnosticLevel>) -> AnnotationKind {
+		// The last mapping of a level wins
+		if let Some(mapping) = level.and_then(|level| {
+			self.map_level
+				.iter()
+				.rev()
+				.find(|mapping| mapping.from == level)
+		}) {
+			return mapping.to;
+		}
+		match (kind, self.downgrade_errors_to) {
+			(AnnotationKind::Error, Some(downgraded)) => downgraded.into(),
+			_ => kind,
 		}
 	}
-}
 
-/// Writes a summary of the job in the special summary file
-fn write_summaries(summaries: Vec<Summary>) -> io::Result<()> {
-	/// Environment variable containing the path to the special summary file
-	const SUMMARY_PATH_VAR: &str = "GITHUB_STEP_SUMMARY";
-	let Some(path) = std::env::var_os(SUMMARY_PATH_VAR)
-		.or(cfg!(debug_assertions).then(|| OsString::from("SUMMARY.md")))
-		else {
-			return Ok(());
+	/// Emits an error, not attached to any file, noting that Cargo timed out
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn emit_timeout_error(&self, out: &mut impl IoWrite) -> io::Result<()> {
+		self.emit_message(
+			AnnotationKind::Error,
+			&format!(
+				"Cargo was killed after running for {:?}",
+				self.cargo_timeout.unwrap_or_default()
+			),
+			out,
+		)
+	}
+
+	/// Emits a notice, not attached to any file, noting that no diagnostic was annotated
+	///
+	/// Nothing is emitted unless `--emit-empty-success-annotation` is passed and `report` has no annotation.
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn emit_success(&self, report: &Report, out: &mut impl IoWrite) -> io::Result<()> {
+		if self.emit_empty_success_annotation && report.annotations.is_empty() {
+			self.emit_message(AnnotationKind::Notice, "All checks passed", out)?;
+		}
+		Ok(())
+	}
+
+	/// Emits an annotation of `kind`, not attached to any file
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn emit_message(
+		&self,
+		kind: AnnotationKind,
+		message: &str,
+		out: &mut impl IoWrite,
+	) -> io::Result<()> {
+		match self.output_format {
+			OutputFormat::Github => writeln!(out, "::{}::{message}", kind.name()),
+			OutputFormat::Azure => writeln!(
+				out,
+				"##vso[task.logissue type={}]{message}",
+				match kind {
+					AnnotationKind::Error => "error",
+					AnnotationKind::Warning | AnnotationKind::Notice => "warning",
+				}
+			),
+			OutputFormat::Human => writeln!(out, "{}: {message}", kind.name()),
+			// The report must remain valid JSON
+			OutputFormat::Bitbucket | OutputFormat::Sarif => {
+				eprintln!("{}: {message}", kind.name());
+				Ok(())
+			}
+		}
+	}
+
+	/// Checks that the environment is suitable to annotate GitHub Actions
+	///
+	/// The environment variables are looked up with `env`, and the Cargo manifest is searched from `current_dir`.
+	/// A report of the checks is written to `out`, followed by a test annotation.
+	/// Returns `true` if all checks passed.
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing to `out`.
+	fn doctor(
+		&self,
+		env: impl Fn(&str) -> Option<OsString>,
+		current_dir: &Path,
+		out: &mut impl IoWrite,
+	) -> io::Result<bool> {
+		let mut healthy = true;
+		let mut check = |out: &mut dyn IoWrite, result: Result<String, String>| {
+			healthy &= result.is_ok();
+			match result {
+				Ok(message) => writeln!(out, "ok: {message}"),
+				Err(message) => writeln!(out, "error: {message}"),
+			}
 		};
-	let mut file = File::create(path)?;
 
-	let diagnostics = summaries
-		.iter()
-		.filter(|summary| matches!(summary, Summary::Diagnostic { .. }))
-		.collect::<Vec<_>>();
-	if !diagnostics.is_empty() {
-		write_diagnostic_summary(diagnostics, &mut file)?;
+		check(
+			out,
+			env("GITHUB_ACTIONS")
+				.map(|_value| "GITHUB_ACTIONS is set".to_owned())
+				.ok_or_else(|| {
+					"GITHUB_ACTIONS is not set, annotations are only displayed in GitHub Actions"
+						.to_owned()
+				}),
+		)?;
+		check(
+			out,
+			match env("GITHUB_WORKSPACE") {
+				Some(workspace) if Path::new(&workspace).is_dir() => Ok(format!(
+					"GITHUB_WORKSPACE is a directory ({})",
+					Path::new(&workspace).display()
+				)),
+				Some(workspace) => Err(format!(
+					"GITHUB_WORKSPACE is not a directory ({})",
+					Path::new(&workspace).display()
+				)),
+				None => Err("GITHUB_WORKSPACE is not set".to_owned()),
+			},
+		)?;
+		check(
+			out,
+			current_dir
+				.ancestors()
+				.map(|dir| dir.join("Cargo.toml"))
+				.find(|manifest| manifest.is_file())
+				.map(|manifest| format!("Cargo manifest found ({})", manifest.display()))
+				.ok_or_else(|| {
+					format!(
+						"no Cargo manifest found in {} or its ancestors",
+						current_dir.display()
+					)
+				}),
+		)?;
+		check(
+			out,
+			match env("GITHUB_STEP_SUMMARY") {
+				Some(path) => OpenOptions::new()
+					.append(true)
+					.create(true)
+					.open(&path)
+					.map(|_file| {
+						format!(
+							"GITHUB_STEP_SUMMARY is writable ({})",
+							Path::new(&path).display()
+						)
+					})
+					.map_err(|err| {
+						format!(
+							"GITHUB_STEP_SUMMARY is not writable ({}): {err}",
+							Path::new(&path).display()
+						)
+					}),
+				None => {
+					Err("GITHUB_STEP_SUMMARY is not set, no summary will be written".to_owned())
+				}
+			},
+		)?;
+
+		self.emit_message(
+			AnnotationKind::Notice,
+			"This test annotation was emitted by `cargo ghannotate doctor`",
+			out,
+		)?;
+		Ok(healthy)
 	}
 
-	Ok(())
+	/// Returns `true` if the job should fail
+	///
+	/// The decision is based on the levels of the parsed diagnostics,
+	/// so that diagnostics reported across several crates (e.g. with `--keep-going`) are weighted by their own severity.
+	/// A Cargo failure that is not explained by any error diagnostic (e.g. a linker error) still fails the job.
+	/// Errors downgraded by `--downgrade-errors-to` or `--map-level`, or ignored by `--ignore-file` explain a Cargo failure without failing the job.
+	///
+	/// If `--max-errors` or `--max-warnings` is passed, the corresponding kind fails the job only past this number of annotations.
+	/// A Cargo invocation that `timed_out` always fails the job, whatever the diagnostics.
+	///
+	/// If `--exit-zero-on-no-diagnostics-even-if-cargo-fails` is passed, the outcome of Cargo (including a timeout) is not taken into account at all.
+	#[inline]
+	fn should_fail(&self, report: &Report, cargo_success: bool, timed_out: bool) -> bool {
+		let errors = report.count(AnnotationKind::Error);
+		let warnings = report.count(AnnotationKind::Warning);
+		let errors_fail = self
+			.max_errors
+			.map_or(errors > 0, |max_errors| errors > max_errors);
+		let warnings_fail = self
+			.max_warnings
+			.map_or(!self.allow_warnings && warnings > 0, |max_warnings| {
+				warnings > max_warnings
+			});
+
+		let cargo_fail = !self.exit_zero_on_no_diagnostics_even_if_cargo_fails
+			&& (timed_out || (!cargo_success && errors == 0 && report.hidden_errors == 0));
+
+		errors_fail || warnings_fail || cargo_fail
+	}
 }
 
-/// Write a summary of the [`Diagnostic`](Summary::Diagnostic) items
-fn write_diagnostic_summary<'s>(
-	diagnostics: impl IntoIterator<Item = &'s Summary>,
-	file: &mut File,
-) -> io::Result<()> {
-	writeln!(file, "# Diagnostics")?;
+/// Options of the conversion of Cargo's messages into annotations
+#[derive(Debug, Clone, Default, Args)]
+struct Options {
+	/// Format of the JSON messages read
+	#[arg(long, value_enum, value_name = "FORMAT", default_value_t = InputFormat::CargoJson)]
+	input_format: InputFormat,
+	/// Strip this prefix from the file names of the spans (tried in order)
+	#[arg(long, value_name = "PREFIX")]
+	strip_span_prefix: Vec<String>,
+	/// Include the captured output of failed tests in their annotations
+	#[arg(long)]
+	annotate_test_output: bool,
+	/// Annotate the location of the `-->` line of the rendered diagnostics rather than their primary span
+	#[arg(long)]
+	prefer_rendered_spans: bool,
+	/// Do not follow symbolic links to make the absolute file paths relative to the workspace
+	#[arg(long)]
+	no_canonicalize: bool,
+}
+impl Options {
+	/// Converts a single JSON message outputted by Cargo into an annotation and its summary
+	///
+	/// Returns `None` if the message cannot be parsed or annotated.
+	/// Any trailing text after the message (e.g. interleaved output of a test harness) is ignored.
+	fn annotate<'c>(&self, json: &'c str) -> Option<(Annotation<'c>, Summary)> {
+		if let Some(Ok(mut message)) = serde_json::Deserializer::from_str(json)
+			.into_iter::<CargoMessage>()
+			.next()
+		{
+			let package_id = match &mut message {
+				CargoMessage::CompilerMessage(CompilerMessage {
+					package_id,
+					message,
+				}) => {
+					for span in &mut message.spans {
+						span.file_name = self.normalize_file_name(span.file_name);
+					}
+					*package_id
+				}
+			};
+			let summary = Summary::from(&message);
+			let mut annotation = Annotation::try_from(message).ok()?;
+			resolve_package_path(&mut annotation, package_id);
+			self.relativize_file(&mut annotation);
+			if self.prefer_rendered_spans {
+				self.relocate_to_rendered(&mut annotation);
+			}
+			Some((annotation, summary))
+		} else if let Some(Ok(message)) = serde_json::Deserializer::from_str(json)
+			.into_iter::<TestMessage>()
+			.next()
+		{
+			let summary = Summary::from(&message);
+			let mut annotation = Annotation::try_from(&message).ok()?;
+			let TestMessage::Test(event) = &message;
+			if let Some(stdout) = event.stdout().filter(|_| self.annotate_test_output) {
+				annotation.message = Cow::Borrowed(stdout);
+			}
+			self.relativize_file(&mut annotation);
+			Some((annotation.to_owned(), summary))
+		} else {
+			None
+		}
+	}
 
-	let mut kind_count: HashMap<AnnotationKind, usize> = HashMap::new();
-	let mut table = String::new();
-	writeln!(table, "|Level|Message|Location|").unwrap();
-	writeln!(table, "|:--|:--|--:|").unwrap();
-	for summary in diagnostics {
-		let Summary::Diagnostic { level, message, location } = summary else {
-			unreachable!()
+	/// Moves `annotation` to the location of the `-->` line of its rendered message, if it differs from its span
+	fn relocate_to_rendered(&self, annotation: &mut Annotation) {
+		let Some((file, line, col)) = rendered_location(&annotation.message) else {
+			return;
 		};
-		let kind = AnnotationKind::from(*level);
-		*kind_count.entry(kind).or_default() += 1;
-		let location = location
-			.as_ref()
-			.map(|location| format!("`{}:{}`", location.0, location.1))
-			.unwrap_or_default();
-		writeln!(table, "|{kind}|{message}|{location}|").unwrap();
+		let file = self.normalize_file_name(file);
+		if file != annotation.file || line != annotation.line || Some(col) != annotation.col {
+			annotation.file = Cow::Owned(file.to_owned());
+			annotation.line = line;
+			annotation.end_line = Some(line);
+			annotation.col = Some(col);
+			annotation.end_column = None;
+		}
 	}
 
-	writeln!(
-		file,
-		"> **TOTAL:** {} {}s, {} {}s, {} {}s",
-		kind_count
-			.get(&AnnotationKind::Error)
-			.copied()
-			.unwrap_or_default(),
-		AnnotationKind::Error,
-		kind_count
-			.get(&AnnotationKind::Warning)
-			.copied()
-			.unwrap_or_default(),
-		AnnotationKind::Warning,
-		kind_count
-			.get(&AnnotationKind::Notice)
-			.copied()
-			.unwrap_or_default(),
-		AnnotationKind::Notice,
-	)?;
-	writeln!(file)?;
-	file.write_all(table.as_bytes())
+	/// Makes the file of `annotation` relative to the [workspace root](workspace_root), if it is an absolute path inside of it
+	fn relativize_file(&self, annotation: &mut Annotation) {
+		let file = Path::new(annotation.file.as_ref());
+		if !file.is_absolute() {
+			return;
+		}
+		if let Some(relative) =
+			workspace_root().and_then(|root| relativize(file, &root, !self.no_canonicalize))
+		{
+			annotation.file = Cow::Owned(relative.to_string_lossy().into_owned());
+		}
+	}
+
+	/// Strips the first matching prefix of `--strip-span-prefix` from `file_name`
+	#[inline]
+	fn normalize_file_name<'f>(&self, file_name: &'f str) -> &'f str {
+		self.strip_span_prefix
+			.iter()
+			.find_map(|prefix| file_name.strip_prefix(prefix.as_str()))
+			.unwrap_or(file_name)
+	}
 }
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use clap::CommandFactory;
+/// Resolves the file of `annotation` in the directory of the package identified by `package_id`
+///
+/// Files that exist relative to the current directory (i.e. the root of the repository) are left untouched.
+/// The resolved files are absolute, until they are [relativized](Options::relativize_file).
+fn resolve_package_path(annotation: &mut Annotation, package_id: &str) {
+	let file = Path::new(annotation.file.as_ref());
+	if !annotation.has_location() || file.is_absolute() || file.exists() {
+		return;
+	}
+	let Some(resolved) = package_dir(package_id)
+		.map(|dir| Path::new(dir).join(file))
+		.filter(|resolved| resolved.exists())
+	else {
+		return;
+	};
+	annotation.file = Cow::Owned(resolved.to_string_lossy().into_owned());
+}
 
-	#[test]
-	fn cli() {
-		Cli::command().debug_assert();
+/// Returns the root of the workspace, against which the annotated files are relative
+///
+/// It is `GITHUB_WORKSPACE` if it is set, or the current directory.
+fn workspace_root() -> Option<PathBuf> {
+	std::env::var_os("GITHUB_WORKSPACE")
+		.map(PathBuf::from)
+		.or_else(|| std::env::current_dir().ok())
+}
+
+/// Returns a note explaining that the file of `annotation` does not exist under `root`, if it does not
+///
+/// GitHub would only display the annotation in the log, rather than inline.
+fn missing_file_note(annotation: &Annotation, root: &Path) -> Option<String> {
+	(annotation.has_location() && !root.join(annotation.file.as_ref()).is_file()).then(|| {
+		format!(
+			"the annotated file `{}` does not exist in `{}`, so the annotation is not displayed inline",
+			annotation.file,
+			root.display()
+		)
+	})
+}
+
+/// Returns `file` spelled like the file on disk, looking it up from `root` (see `--normalize-path-case`)
+///
+/// Each component is matched case-insensitively against the entries of its parent directory, preferring an exact match.
+/// Returns `None` if a component does not match any entry.
+fn real_path_case(file: &str, root: &Path) -> Option<String> {
+	let mut dir = root.to_path_buf();
+	let mut real = String::with_capacity(file.len());
+	for (i, component) in file.split_inclusive(['/', '\\']).enumerate() {
+		let name = component.trim_end_matches(['/', '\\']);
+		if name.is_empty() || name == "." || name == ".." || name.contains(':') {
+			if i == 0 || !name.is_empty() {
+				dir.push(component);
+			}
+			real.push_str(component);
+			continue;
+		}
+		let lowercase = name.to_lowercase();
+		let mut entry_name = None;
+		for entry in fs::read_dir(&dir).ok()? {
+			let entry = entry.ok()?.file_name();
+			let Some(entry) = entry.to_str() else {
+				continue;
+			};
+			if entry == name {
+				entry_name = Some(entry.to_owned());
+				break;
+			}
+			if entry_name.is_none() && entry.to_lowercase() == lowercase {
+				entry_name = Some(entry.to_owned());
+			}
+		}
+		let entry_name = entry_name?;
+		dir.push(&entry_name);
+		real.push_str(&entry_name);
+		real.push_str(&component[name.len()..]);
+	}
+	Some(real)
+}
+
+/// Returns `path` relative to `root`, if it is inside of it
+///
+/// If `canonicalize` is `true`, symbolic links are followed in both paths before comparing them.
+/// Paths that cannot be canonicalized (e.g. because they do not exist) are compared lexically.
+fn relativize(path: &Path, root: &Path, canonicalize: bool) -> Option<PathBuf> {
+	if canonicalize {
+		if let (Ok(path), Ok(root)) = (path.canonicalize(), root.canonicalize()) {
+			if let Ok(relative) = path.strip_prefix(root) {
+				return Some(relative.to_path_buf());
+			}
+		}
+	}
+	path.strip_prefix(root).ok().map(Path::to_path_buf)
+}
+
+/// Lazily converts the JSON messages read from `reader` into annotations
+///
+/// The input is read as the annotations are pulled, without buffering it whole.
+/// Messages that cannot be parsed or annotated are skipped, except the compiled artifacts,
+/// but no deduplication is performed.
+fn annotations<'o, R: BufRead + 'o>(
+	reader: R,
+	options: &'o Options,
+) -> impl Iterator<Item = Result<Message, ProcessError>> + 'o {
+	let messages: Box<dyn Iterator<Item = io::Result<String>> + 'o> = match options.input_format {
+		InputFormat::CargoJson => Box::new(reader.lines()),
+		InputFormat::Json => Box::new(JsonObjects::new(reader)),
+	};
+	messages.filter_map(|json| match json {
+		Ok(json) => match options.annotate(&json) {
+			Some((annotation, summary)) => Some(
+				annotation
+					.validate()
+					.map(|()| Message::Annotation(annotation.to_owned(), summary))
+					.map_err(ProcessError::Invalid),
+			),
+			None => match serde_json::Deserializer::from_str(&json)
+				.into_iter::<ArtifactMessage>()
+				.next()
+			{
+				Some(Ok(ArtifactMessage::CompilerArtifact { package_id })) => {
+					Some(Ok(Message::Artifact(package_id.to_owned())))
+				}
+				_ => None,
+			},
+		},
+		Err(err) => Some(Err(ProcessError::Io(err))),
+	})
+}
+
+/// JSON message converted by [`annotations`]
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // Most messages are annotations
+enum Message {
+	/// Annotation of a diagnostic or a failed test, with its summary
+	Annotation(Annotation<'static>, Summary),
+	/// A crate of the package identified by this ID was compiled (see `--summary-include-passing-crates`)
+	Artifact(String),
+}
+
+/// Error that occurred while processing the messages outputted by Cargo
+#[derive(Debug)]
+enum ProcessError {
+	/// The input could not be read
+	Io(io::Error),
+	/// The annotation violates a constraint of GitHub
+	Invalid(AnnotationError),
+}
+impl Display for ProcessError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io(err) => write!(f, "could not read input: {err}"),
+			Self::Invalid(err) => write!(f, "invalid annotation: {err}"),
+		}
+	}
+}
+impl Error for ProcessError {
+	#[inline]
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Io(err) => Some(err),
+			Self::Invalid(err) => Some(err),
+		}
+	}
+}
+
+/// Format of the emitted annotations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+	/// GitHub Actions workflow commands
+	Github,
+	/// Azure Pipelines logging commands
+	Azure,
+	/// Bitbucket Code Insights report
+	Bitbucket,
+	/// SARIF log (e.g. for GitHub code scanning)
+	Sarif,
+	/// Human-readable diagnostics, for a terminal
+	Human,
+}
+
+/// Format of the JSON messages read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+	/// One message per line, as outputted by Cargo
+	#[default]
+	#[value(alias = "ndjson", alias = "json-lines")]
+	CargoJson,
+	/// Concatenated messages or arrays of messages, which may span several lines
+	///
+	/// The messages are read one at a time, and any text outside of them is skipped.
+	#[value(alias = "json-array")]
+	Json,
+}
+
+/// Kind with which errors are emitted instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DowngradedKind {
+	/// Emit errors as warnings
+	Warning,
+	/// Emit errors as notices
+	Notice,
+}
+impl From<DowngradedKind> for AnnotationKind {
+	#[inline]
+	fn from(kind: DowngradedKind) -> Self {
+		match kind {
+			DowngradedKind::Warning => Self::Warning,
+			DowngradedKind::Notice => Self::Notice,
+		}
+	}
+}
+
+/// Mapping of a diagnostic level to the kind of its annotations (see `--map-level`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LevelMapping {
+	/// Level of the mapped diagnostics
+	from: DiagnosticLevel,
+	/// Kind with which the mapped diagnostics are emitted
+	to: AnnotationKind,
+}
+impl LevelMapping {
+	/// Parses a mapping of the form `<level>=<kind>`
+	///
+	/// # Errors
+	/// Returns an error message if the level or the kind is unknown.
+	fn parse(value: &str) -> Result<Self, String> {
+		let (from, to) = value
+			.split_once('=')
+			.ok_or_else(|| format!("expected `<level>=<kind>`, found `{value}`"))?;
+		let from = match from.trim() {
+			"error" => DiagnosticLevel::Error,
+			"warning" => DiagnosticLevel::Warning,
+			"note" => DiagnosticLevel::Note,
+			"help" => DiagnosticLevel::Help,
+			"failure-note" => DiagnosticLevel::FailureNote,
+			"ice" => DiagnosticLevel::InternalCompilerError,
+			level => {
+				return Err(format!(
+					"unknown level `{level}` (expected one of error, warning, note, help, failure-note, ice)"
+				))
+			}
+		};
+		let to = match to.trim() {
+			"error" => AnnotationKind::Error,
+			"warning" => AnnotationKind::Warning,
+			"notice" => AnnotationKind::Notice,
+			kind => {
+				return Err(format!(
+					"unknown kind `{kind}` (expected one of error, warning, notice)"
+				))
+			}
+		};
+		Ok(Self { from, to })
+	}
+}
+
+/// Format of the locations in the summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LocationFormat {
+	/// `file:line`
+	Default,
+	/// `file:line:column`, as rustc
+	Rustc,
+	/// `file(line,column)`, as MSVC
+	Msvc,
+}
+impl LocationFormat {
+	/// Formats `(file, line, column)` as inline code
+	fn format(self, (file, line, column): &(String, usize, usize)) -> String {
+		match self {
+			Self::Default => format!("`{file}:{line}`"),
+			Self::Rustc => format!("`{file}:{line}:{column}`"),
+			Self::Msvc => format!("`{file}({line},{column})`"),
+		}
+	}
+}
+
+/// Presentation of the totals of the summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SummaryTheme {
+	/// A single line of text
+	Plain,
+	/// One chip per kind, and a collapsed bar chart of their distribution
+	Rich,
+}
+
+/// Grouping of the diagnostics of the summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SummaryLayout {
+	/// A single table
+	Flat,
+	/// One table per kind, errors first
+	ByKind,
+	/// One collapsed table per file
+	ByFile,
+	/// One collapsed section per kind, errors first, with one collapsed table per file
+	KindThenFile,
+}
+
+/// Order in which the annotations are emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EmitOrder {
+	/// By file, line and column
+	Location,
+	/// Errors first, then warnings, then notices, each by location
+	Severity,
+}
+
+/// Outcome of the processing of Cargo's output
+#[derive(Debug, Clone)]
+struct Report {
+	/// Emitted annotations
+	annotations: BTreeSet<Annotation<'static>>,
+	/// Summaries of the emitted annotations
+	summaries: Vec<Summary>,
+	/// Most severe kind of the emitted annotations
+	max_annotation: AnnotationKind,
+	/// Summaries of the known issues that were not annotated (see `--ignore-file`)
+	ignored: Vec<Summary>,
+	/// Number of errors not emitted as such (see `--downgrade-errors-to`, `--map-level` and `--ignore-file`)
+	hidden_errors: usize,
+	/// Results of the SARIF log, in the order of emission (see `--output-format=sarif`)
+	sarif_results: Vec<SarifResult>,
+	/// Subcommand whose output is being processed, to label its diagnostics (see `multi`)
+	subcommand: Option<CargoSubcommand>,
+	/// State of the incremental flush of the summary (see `--summary-flush-interval`)
+	summary_flush: Option<SummaryFlush>,
+	/// Packages whose crates were compiled (see `--summary-include-passing-crates`)
+	built_packages: BTreeSet<String>,
+	/// Captured standard error of the failed Cargo invocations (see `--capture-stderr`)
+	cargo_stderr: Vec<u8>,
+}
+impl Report {
+	/// Creates an empty report
+	#[inline]
+	const fn new() -> Self {
+		Self {
+			annotations: BTreeSet::new(),
+			summaries: Vec::new(),
+			max_annotation: AnnotationKind::Notice,
+			ignored: Vec::new(),
+			hidden_errors: 0,
+			sarif_results: Vec::new(),
+			subcommand: None,
+			summary_flush: None,
+			built_packages: BTreeSet::new(),
+			cargo_stderr: Vec::new(),
+		}
+	}
+
+	/// Returns the number of emitted annotations of `kind`
+	#[inline]
+	fn count(&self, kind: AnnotationKind) -> usize {
+		self.annotations
+			.iter()
+			.filter(|annotation| annotation.kind == kind)
+			.count()
+	}
+}
+
+/// State of the incremental flush of the summary
+#[derive(Debug, Clone)]
+struct SummaryFlush {
+	/// Path to the summary file
+	path: PathBuf,
+	/// Minimum interval between two flushes
+	interval: Duration,
+	/// Instant of the last flush
+	flushed_at: Instant,
+}
+
+/// Cargo subcommand
+#[derive(Debug, Clone, Subcommand)]
+enum CliCommand {
+	/// Runs `cargo check` and annotates from its output
+	Check(CliCommandArgs),
+	/// Runs `cargo clippy` and annotates from its output
+	Clippy(CliCommandArgs),
+	/// Runs `cargo build` and annotates from its output
+	Build(CliCommandArgs),
+	/// Runs `cargo test` and annotates from its output
+	Test(CliCommandArgs),
+	/// Runs several Cargo subcommands in turn and annotates from their merged outputs
+	Multi(MultiArgs),
+	/// Checks that the environment is suitable to annotate GitHub Actions
+	#[command(hide = true)]
+	Doctor,
+}
+impl CliCommand {
+	/// Returns the arguments to be passed down to Cargo
+	#[inline]
+	fn args(&self) -> &[OsString] {
+		match self {
+			Self::Check(args) | Self::Clippy(args) | Self::Build(args) | Self::Test(args) => {
+				args.as_ref()
+			}
+			Self::Multi(multi) => &multi.args,
+			Self::Doctor => &[],
+		}
+	}
+}
+
+/// Arguments of the `multi` subcommand
+#[derive(Debug, Clone, Args)]
+struct MultiArgs {
+	/// Cargo subcommands to run, in order
+	#[arg(value_enum, required = true)]
+	commands: Vec<CargoSubcommand>,
+	/// Arguments to be passed down to each Cargo subcommand (after `--`)
+	#[arg(last = true)]
+	args: Vec<OsString>,
+}
+
+/// A running Cargo invocation
+#[derive(Debug)]
+struct CargoProcess {
+	/// Standard output (i.e. the JSON messages), to be read while Cargo runs
+	stdout: BufReader<ChildStdout>,
+	/// Thread waiting for Cargo to exit (see [`wait_timeout`])
+	waiter: JoinHandle<io::Result<Option<ExitStatus>>>,
+	/// Thread teeing the standard error (see `--capture-stderr`)
+	stderr_reader: Option<JoinHandle<io::Result<Vec<u8>>>>,
+}
+impl CargoProcess {
+	/// Waits for Cargo to exit and returns the rest of its output
+	///
+	/// # Errors
+	/// Returns any error that occurred while waiting for Cargo or reading its standard error.
+	fn wait(self) -> io::Result<CargoOutput> {
+		let status = self.waiter.join().expect("Cargo's waiter panicked")?;
+		let stderr = match self.stderr_reader {
+			Some(reader) => reader.join().expect("Cargo's stderr reader panicked")?,
+			None => Vec::new(),
+		};
+		Ok(CargoOutput { stderr, status })
+	}
+}
+
+/// Output of a Cargo invocation, once it exited
+#[derive(Debug, Clone)]
+struct CargoOutput {
+	/// Standard error, if it was captured (see `--capture-stderr`)
+	stderr: Vec<u8>,
+	/// Exit status (`None` if Cargo was killed after `--cargo-timeout`)
+	status: Option<ExitStatus>,
+}
+
+/// Cargo subcommand that outputs JSON messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CargoSubcommand {
+	/// `cargo check`
+	Check,
+	/// `cargo clippy`
+	Clippy,
+	/// `cargo build`
+	Build,
+	/// `cargo test`
+	Test,
+}
+impl CargoSubcommand {
+	/// Returns the name of the subcommand
+	#[inline]
+	const fn name(self) -> &'static str {
+		match self {
+			Self::Check => "check",
+			Self::Clippy => "clippy",
+			Self::Build => "build",
+			Self::Test => "test",
+		}
+	}
+}
+
+/// Arguments to be passed down to Cargo
+#[derive(Debug, Clone, Args)]
+#[repr(transparent)]
+struct CliCommandArgs {
+	/// Arguments to be passed down to Cargo
+	#[arg(
+		num_args = 0..,
+		trailing_var_arg = true,
+		allow_hyphen_values = true,
+	)]
+	args: Vec<OsString>,
+}
+impl AsRef<[OsString]> for CliCommandArgs {
+	#[inline]
+	fn as_ref(&self) -> &[OsString] {
+		self.args.as_ref()
+	}
+}
+
+/// Returns warnings about the arguments passed down to Cargo that are known to suppress its JSON diagnostics
+///
+/// The arguments after `--` are passed down to the compiler, so they are not checked.
+fn cargo_args_warnings(args: &[OsString]) -> Vec<String> {
+	let mut warnings = Vec::new();
+	let mut args = args.iter().map(|arg| arg.to_string_lossy());
+	while let Some(arg) = args.next() {
+		match arg.as_ref() {
+			"--" => break,
+			"--unit-graph" | "--build-plan" => warnings.push(format!(
+				"`{arg}` makes Cargo output a plan instead of compiling, so no diagnostic will be annotated"
+			)),
+			_ => {
+				let format = match arg.strip_prefix("--message-format") {
+					Some("") => args.next(),
+					Some(format) => format.strip_prefix('=').map(Cow::Borrowed),
+					None => None,
+				};
+				if let Some(format) = format {
+					if !format.starts_with("json") {
+						warnings.push(format!(
+							"`{arg}` conflicts with the `--message-format=json` passed to Cargo, so its diagnostics may not be annotated"
+						));
+					}
+				}
+			}
+		}
+	}
+	warnings
+}
+
+/// Watcher of the changes of the source files under a directory (see `--watch`)
+///
+/// The modification times of the files are polled.
+#[derive(Debug, Clone)]
+struct SourceWatcher {
+	/// Watched directory
+	root: PathBuf,
+	/// Modification times of the source files, as of the last poll
+	snapshot: BTreeMap<PathBuf, SystemTime>,
+	/// Interval between two polls
+	poll_interval: Duration,
+	/// Duration without further changes after which a change is reported
+	debounce: Duration,
+}
+impl SourceWatcher {
+	/// Starts watching the source files under `root`
+	///
+	/// # Errors
+	/// Returns any error that occurred while listing the source files.
+	fn new(root: PathBuf) -> io::Result<Self> {
+		Ok(Self {
+			snapshot: source_snapshot(&root)?,
+			root,
+			poll_interval: Duration::from_millis(500),
+			debounce: Duration::from_millis(300),
+		})
+	}
+
+	/// Waits for a source file to be added, modified or removed
+	///
+	/// It returns once no further change happened for the debounce duration,
+	/// so that a burst of changes (e.g. a formatter touching many files) is reported once.
+	///
+	/// # Errors
+	/// Returns any error that occurred while listing the source files.
+	fn wait(&mut self) -> io::Result<()> {
+		while !self.poll()? {}
+		let mut last_change = Instant::now();
+		while last_change.elapsed() < self.debounce {
+			if self.poll()? {
+				last_change = Instant::now();
+			}
+		}
+		Ok(())
+	}
+
+	/// Sleeps for the poll interval, then returns `true` if the source files changed since the last poll
+	///
+	/// # Errors
+	/// Returns any error that occurred while listing the source files.
+	fn poll(&mut self) -> io::Result<bool> {
+		thread::sleep(self.poll_interval);
+		let snapshot = source_snapshot(&self.root)?;
+		let changed = snapshot != self.snapshot;
+		self.snapshot = snapshot;
+		Ok(changed)
+	}
+}
+
+/// Returns the modification times of the source files (`.rs` and `.toml`) under `root`
+///
+/// The hidden and `target` directories are skipped.
+///
+/// # Errors
+/// Returns any error that occurred while reading the directories.
+fn source_snapshot(root: &Path) -> io::Result<BTreeMap<PathBuf, SystemTime>> {
+	let mut snapshot = BTreeMap::new();
+	let mut dirs = vec![root.to_path_buf()];
+	while let Some(dir) = dirs.pop() {
+		for entry in std::fs::read_dir(dir)? {
+			let entry = entry?;
+			let path = entry.path();
+			let name = entry.file_name();
+			let name = name.to_string_lossy();
+			if entry.file_type()?.is_dir() {
+				if name != "target" && !name.starts_with('.') {
+					dirs.push(path);
+				}
+			} else if name.ends_with(".rs") || name.ends_with(".toml") {
+				// The file may have been removed since the directory was read
+				if let Ok(modified) = entry.metadata().and_then(|metadata| metadata.modified()) {
+					snapshot.insert(path, modified);
+				}
+			}
+		}
+	}
+	Ok(snapshot)
+}
+
+/// Waits for `child` to exit for at most `timeout`
+///
+/// If the timeout elapses, `child` is killed along with its process group, and `None` is returned.
+///
+/// # Errors
+/// Returns any error that occurred while waiting for or killing `child`.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+	/// Maximum interval between two checks of the status of `child`
+	const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+	let start = Instant::now();
+	loop {
+		if let Some(status) = child.try_wait()? {
+			return Ok(Some(status));
+		}
+		let elapsed = start.elapsed();
+		if elapsed >= timeout {
+			break;
+		}
+		thread::sleep(POLL_INTERVAL.min(timeout - elapsed));
+	}
+
+	// The child was spawned as the leader of its own process group
+	#[cfg(unix)]
+	let _ = Command::new("kill")
+		.args(["-s", "KILL", "--", &format!("-{}", child.id())])
+		.stderr(Stdio::null())
+		.status();
+	// The child may already be dead if its process group was killed
+	let _ = child.kill();
+	child.wait()?;
+	Ok(None)
+}
+
+/// Parses a duration made of numbers followed by units (e.g. `1h30m`)
+///
+/// The supported units are `ms`, `s`, `m`, `h` and `d`.
+///
+/// # Errors
+/// Returns an error if `value` is not a valid duration.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+	let mut duration = Duration::ZERO;
+	let mut rest = value.trim();
+	if rest.is_empty() {
+		return Err("empty duration".to_owned());
+	}
+	while !rest.is_empty() {
+		let digits = rest
+			.find(|c: char| !c.is_ascii_digit())
+			.ok_or_else(|| format!("missing unit after `{rest}`"))?;
+		let number = rest[..digits]
+			.parse::<u64>()
+			.map_err(|err| format!("invalid number in `{value}`: {err}"))?;
+		rest = &rest[digits..];
+		let units = rest
+			.find(|c: char| c.is_ascii_digit())
+			.unwrap_or(rest.len());
+		duration += match &rest[..units] {
+			"ms" => Duration::from_millis(number),
+			"s" => Duration::from_secs(number),
+			"m" => Duration::from_secs(number * 60),
+			"h" => Duration::from_secs(number * 60 * 60),
+			"d" => Duration::from_secs(number * 60 * 60 * 24),
+			unit => return Err(format!("unknown unit `{unit}`")),
+		};
+		rest = &rest[units..];
+	}
+	Ok(duration)
+}
+
+/// Returns `true` if `path` exists and was modified within `duration`
+fn modified_within(path: impl AsRef<Path>, duration: Duration) -> bool {
+	std::fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.is_ok_and(|modified| {
+			SystemTime::now()
+				.duration_since(modified)
+				.map_or(true, |elapsed| elapsed <= duration)
+		})
+}
+
+/// Returns the number of lines of the file at `path`, if it can be read
+fn line_count(path: impl AsRef<Path>) -> Option<usize> {
+	let file = File::open(path).ok()?;
+	Some(BufReader::new(file).lines().count())
+}
+
+/// Returns the path to the summary file, if any
+///
+/// It is `GITHUB_STEP_SUMMARY`, or `SUMMARY.md` in debug builds.
+fn summary_path() -> Option<PathBuf> {
+	/// Environment variable containing the path to the special summary file
+	const SUMMARY_PATH_VAR: &str = "GITHUB_STEP_SUMMARY";
+	std::env::var_os(SUMMARY_PATH_VAR)
+		.map(PathBuf::from)
+		.or(cfg!(debug_assertions).then(|| PathBuf::from("SUMMARY.md")))
+}
+
+/// Maps the IDs of the packages described by `cargo metadata` to their names
+///
+/// # Errors
+/// Returns an error if `metadata` is not valid `cargo metadata` output.
+fn package_names(metadata: &[u8]) -> serde_json::Result<HashMap<String, String>> {
+	Ok(serde_json::from_slice::<Metadata>(metadata)?
+		.packages
+		.into_iter()
+		.map(|package| (package.id.to_owned(), package.name.to_owned()))
+		.collect())
+}
+
+/// Summary of [`CargoMessage`]
+#[derive(Debug, Clone)]
+enum Summary {
+	/// Summary of [`Diagnostic`](cargo::Diagnostic)
+	Diagnostic {
+		/// [`CompilerMessage.package_id`](CompilerMessage#structfield.package_id)
+		package_id: String,
+		/// [`Diagnostic.code`](cargo::Diagnostic#structfield.code)
+		code: Option<String>,
+		/// [`Diagnostic.level`](cargo::Diagnostic#structfield.level)
+		level: DiagnosticLevel,
+		/// [`Diagnostic.message`](cargo::Diagnostic#structfield.message)
+		message: String,
+		/// Location of the diagnostic (primary [span](cargo::DiagnosticSpan)), as `(file, line, column)`
+		location: Option<(String, usize, usize)>,
+		/// [`Diagnostic.rendered`](cargo::Diagnostic#structfield.rendered)
+		rendered: Option<String>,
+	},
+	/// Summary of a failed test
+	Test {
+		/// Name of the test
+		name: String,
+		/// Location where the test panicked, as `(file, line, column)`
+		location: Option<(String, usize, usize)>,
+	},
+}
+impl<'c> From<&'c CompilerMessage<'c>> for Summary {
+	#[inline]
+	fn from(compiler_message: &'c CompilerMessage<'c>) -> Self {
+		let message = &compiler_message.message;
+		Self::Diagnostic {
+			package_id: compiler_message.package_id.to_owned(),
+			code: message.code.map(|code| code.code.to_owned()),
+			level: message.level,
+			message: message.message.to_owned(),
+			location: message.spans.iter().find_map(|span| {
+				(span.is_primary && !span.file_name.is_empty()).then(|| {
+					(
+						span.file_name.to_owned(),
+						span.line_start,
+						span.column_start,
+					)
+				})
+			}),
+			rendered: message.rendered.as_deref().map(str::to_owned),
+		}
+	}
+}
+impl<'c> From<&'c CargoMessage<'c>> for Summary {
+	#[inline]
+	fn from(message: &'c CargoMessage<'c>) -> Self {
+		match message {
+			CargoMessage::CompilerMessage(message) => Self::from(message),
+		}
+	}
+}
+impl<'c> From<&'c TestMessage<'c>> for Summary {
+	#[inline]
+	fn from(message: &'c TestMessage<'c>) -> Self {
+		match message {
+			TestMessage::Test(event @ TestEvent::Failed { name, .. }) => Self::Test {
+				name: name.clone().into_owned(),
+				location: event
+					.panic_location()
+					.map(|(file, line, column)| (file.to_owned(), line, column)),
+			},
+		}
+	}
+}
+
+impl Cli {
+	/// Writes a summary of the job in the special summary file
+	///
+	/// Crates are labelled with their names from `package_names`, if any.
+	fn write_summaries(
+		&self,
+		report: &Report,
+		package_names: &HashMap<String, String>,
+	) -> io::Result<()> {
+		let Some(path) = summary_path() else {
+			return Ok(());
+		};
+		self.write_summary_file(&path, report, package_names)
+	}
+
+	/// Writes a summary of the job in the file at `path`
+	///
+	/// The file is overwritten, unless `--concurrency-safe-summary` is passed:
+	/// the summary is then appended to the file while holding an exclusive lock on it.
+	fn write_summary_file(
+		&self,
+		path: &Path,
+		report: &Report,
+		package_names: &HashMap<String, String>,
+	) -> io::Result<()> {
+		let summary = self.summary(report, package_names)?;
+		if self.concurrency_safe_summary {
+			let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+			if let Err(err) = file.lock() {
+				eprintln!("warning: the summary file could not be locked ({err})");
+			}
+			// The lock is released when the file is closed
+			file.write_all(&summary)
+		} else {
+			File::create(path)?.write_all(&summary)
+		}
+	}
+
+	/// Writes the summary of the report into a buffer
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing the summary.
+	fn summary(
+		&self,
+		report: &Report,
+		package_names: &HashMap<String, String>,
+	) -> io::Result<Vec<u8>> {
+		let mut summary = Vec::new();
+		let diagnostics = report
+			.summaries
+			.iter()
+			.filter(|summary| matches!(summary, Summary::Diagnostic { .. }))
+			.collect::<Vec<_>>();
+		if !diagnostics.is_empty() {
+			if self.reformat_message {
+				self.write_diagnostic_summary(
+					diagnostics.iter().copied(),
+					package_names,
+					&mut summary,
+				)?;
+				write_rendered_summary(diagnostics, &mut summary)?;
+			} else {
+				self.write_diagnostic_summary(diagnostics, package_names, &mut summary)?;
+			}
+		}
+		if self.summary_include_passing_crates {
+			self.write_crates_summary(report, package_names, &mut summary)?;
+		}
+		let tests = report
+			.summaries
+			.iter()
+			.filter(|summary| matches!(summary, Summary::Test { .. }))
+			.collect::<Vec<_>>();
+		if !tests.is_empty() {
+			write_test_summary(tests, self.location_format, &mut summary)?;
+		}
+		if !report.ignored.is_empty() {
+			self.write_ignored_summary(&report.ignored, package_names, &mut summary)?;
+		}
+		if !report.cargo_stderr.is_empty() && report.count(AnnotationKind::Error) == 0 {
+			write_build_output_summary(&report.cargo_stderr, &mut summary)?;
+		}
+		Ok(summary)
+	}
+
+	/// Overwrites the summary file with a partial summary of `report`, if `--summary-flush-interval` elapsed since the last flush
+	///
+	/// The partial summary is marked as such, and it lacks the crate names.
+	/// It is eventually overwritten by the complete summary, unless the run is interrupted.
+	///
+	/// # Errors
+	/// Returns any error that occurred while writing the summary file.
+	fn flush_summary(&self, report: &mut Report) -> io::Result<()> {
+		let Some(flush) = report
+			.summary_flush
+			.as_ref()
+			.filter(|flush| flush.flushed_at.elapsed() >= flush.interval)
+		else {
+			return Ok(());
+		};
+		let mut summary =
+			b"> :hourglass: **Partial summary:** the run has not completed\n\n".to_vec();
+		summary.extend(self.summary(report, &HashMap::new())?);
+		File::create(&flush.path)?.write_all(&summary)?;
+
+		if let Some(flush) = &mut report.summary_flush {
+			flush.flushed_at = Instant::now();
+		}
+		Ok(())
+	}
+
+	/// Write a summary of the [`Diagnostic`](Summary::Diagnostic) items
+	///
+	/// A column with the crate names is added if `package_names` is not empty.
+	fn write_diagnostic_summary<'s>(
+		&self,
+		diagnostics: impl IntoIterator<Item = &'s Summary>,
+		package_names: &HashMap<String, String>,
+		file: &mut impl IoWrite,
+	) -> io::Result<()> {
+		writeln!(file, "# Diagnostics")?;
+
+		let diagnostics = diagnostics.into_iter().collect::<Vec<_>>();
+		let files = diagnostics
+			.iter()
+			.filter_map(|summary| match summary {
+				Summary::Diagnostic {
+					location: Some((file, _line, _column)),
+					..
+				} => Some(file),
+				_ => None,
+			})
+			.collect::<BTreeSet<_>>()
+			.len();
+		let rows = self.diagnostic_rows(diagnostics, package_names);
+		let mut kind_count: HashMap<AnnotationKind, usize> = HashMap::new();
+		for row in &rows {
+			*kind_count.entry(row.kind).or_default() += 1;
+		}
+		let stats = AnnotationStats::new(&kind_count, files);
+
+		match self.summary_theme {
+			SummaryTheme::Plain => writeln!(
+				file,
+				"{}",
+				stats.render(
+					self.summary_footer_template
+						.as_deref()
+						.unwrap_or(DEFAULT_SUMMARY_FOOTER_TEMPLATE)
+				)
+			)?,
+			SummaryTheme::Rich => {
+				write_rich_totals(&kind_count, file)?;
+				if let Some(template) = &self.summary_footer_template {
+					writeln!(file)?;
+					writeln!(file, "{}", stats.render(template))?;
+				}
+			}
+		}
+		writeln!(file)?;
+
+		let header = diagnostic_table_header(package_names, !self.summary_no_location_column);
+		let layout = if self.summary_split_by_kind {
+			SummaryLayout::ByKind
+		} else {
+			self.summary_layout
+		};
+		match layout {
+			SummaryLayout::Flat => {
+				file.write_all(header.as_bytes())?;
+				for row in &rows {
+					file.write_all(row.markdown.as_bytes())?;
+				}
+			}
+			SummaryLayout::ByFile => write_file_groups(file, header, &rows)?,
+			SummaryLayout::ByKind | SummaryLayout::KindThenFile => {
+				write_kind_groups(file, header, &rows, layout)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Write the numbers of diagnostics of each built crate, including the crates without any
+	///
+	/// Only the built crates of the workspace are listed, i.e. the ones in `package_names` if it is not empty,
+	/// or else the local ones.
+	/// Crates are labelled with their names from `package_names`, or from their package IDs.
+	fn write_crates_summary(
+		&self,
+		report: &Report,
+		package_names: &HashMap<String, String>,
+		file: &mut impl IoWrite,
+	) -> io::Result<()> {
+		let mut counts: BTreeMap<&str, HashMap<AnnotationKind, usize>> = report
+			.built_packages
+			.iter()
+			.filter(|package_id| {
+				if package_names.is_empty() {
+					package_dir(package_id).is_some()
+				} else {
+					package_names.contains_key(package_id.as_str())
+				}
+			})
+			.map(|package_id| (package_id.as_str(), HashMap::new()))
+			.collect();
+		for summary in &report.summaries {
+			if let Summary::Diagnostic {
+				package_id, level, ..
+			} = summary
+			{
+				*counts
+					.entry(package_id)
+					.or_default()
+					.entry(self.emitted_kind(AnnotationKind::from(*level), Some(*level)))
+					.or_default() += 1;
+			}
+		}
+
+		writeln!(file, "# Crates")?;
+		writeln!(
+			file,
+			"|Crate|{}s|{}s|{}s|",
+			AnnotationKind::Error,
+			AnnotationKind::Warning,
+			AnnotationKind::Notice
+		)?;
+		writeln!(file, "|:--|--:|--:|--:|")?;
+		for (package_id, kind_count) in counts {
+			let name = package_names
+				.get(package_id)
+				.map_or_else(|| package_name(package_id), String::as_str);
+			let count = |kind| kind_count.get(&kind).copied().unwrap_or_default();
+			writeln!(
+				file,
+				"|`{name}`|{}|{}|{}|",
+				count(AnnotationKind::Error),
+				count(AnnotationKind::Warning),
+				count(AnnotationKind::Notice)
+			)?;
+		}
+		writeln!(file)
+	}
+
+	/// Write a summary of the known issues that were not annotated (see `--ignore-file`)
+	///
+	/// The table is collapsed by default.
+	fn write_ignored_summary(
+		&self,
+		ignored: &[Summary],
+		package_names: &HashMap<String, String>,
+		file: &mut impl IoWrite,
+	) -> io::Result<()> {
+		writeln!(file, "<details><summary>Known issues (ignored)</summary>")?;
+		writeln!(file)?;
+		file.write_all(
+			diagnostic_table_header(package_names, !self.summary_no_location_column).as_bytes(),
+		)?;
+		for row in self.diagnostic_rows(ignored, package_names) {
+			file.write_all(row.markdown.as_bytes())?;
+		}
+		writeln!(file)?;
+		writeln!(file, "</details>")
+	}
+
+	/// Builds the rows of the table of the [`Diagnostic`](Summary::Diagnostic) items
+	///
+	/// The rows match the [header](diagnostic_table_header) for `package_names` and `--summary-no-location-column`.
+	fn diagnostic_rows<'s>(
+		&self,
+		diagnostics: impl IntoIterator<Item = &'s Summary>,
+		package_names: &HashMap<String, String>,
+	) -> Vec<DiagnosticRow> {
+		let mut rows = Vec::new();
+		for summary in diagnostics {
+			let Summary::Diagnostic {
+				package_id,
+				code,
+				level,
+				message,
+				location,
+				..
+			} = summary
+			else {
+				continue;
+			};
+			let lint = code.as_deref().is_some_and(|code| !is_error_code(code));
+			let original_kind = AnnotationKind::from(*level);
+			let kind = self.emitted_kind(original_kind, Some(*level));
+			let level = if kind != original_kind {
+				format!("{kind} (originally {original_kind:?})")
+			} else if kind == AnnotationKind::Error && lint {
+				format!("{kind} (denied lint)")
+			} else {
+				kind.to_string()
+			};
+			let message = self.summary_message(message);
+			let file = location.as_ref().map(|(file, _line, _column)| file.clone());
+			let location = location
+				.as_ref()
+				.map(|location| self.location_format.format(location))
+				.unwrap_or_default();
+			let mut row = format!("|{level}|");
+			if !package_names.is_empty() {
+				let name = package_names
+					.get(package_id)
+					.map(String::as_str)
+					.unwrap_or_default();
+				write!(row, "{name}|").unwrap();
+			}
+			write!(row, "{message}").unwrap();
+			if let Some(hint) = code
+				.as_deref()
+				.filter(|_| self.summary_hints)
+				.and_then(error_hint)
+			{
+				write!(row, "<br>:bulb: {hint}").unwrap();
+			}
+			row.push('|');
+			if !self.summary_no_location_column {
+				write!(row, "{location}|").unwrap();
+			}
+			row.push('\n');
+			rows.push(DiagnosticRow {
+				kind,
+				lint,
+				file,
+				markdown: row,
+			});
+		}
+		rows
+	}
+
+	/// Shortens `message` to fit in a cell of the summary
+	///
+	/// Only the first line is kept, and it is truncated to `--summary-max-message-chars`.
+	fn summary_message<'m>(&self, message: &'m str) -> Cow<'m, str> {
+		let first_line = message.lines().next().unwrap_or_default();
+		match self.summary_max_message_chars {
+			Some(max_chars) if first_line.chars().count() > max_chars => first_line
+				.chars()
+				.take(max_chars.saturating_sub(1))
+				.chain(['…'])
+				.collect(),
+			_ => Cow::Borrowed(first_line),
+		}
+	}
+}
+
+/// Returns a hint to resolve the errors of `code`, if it is a common one (see `--summary-hints`)
+fn error_hint(code: &str) -> Option<&'static str> {
+	Some(match code {
+		"E0432" => "Is the crate added to `Cargo.toml`, and is the path of the `use` right?",
+		"E0433" => "Is the crate added to `Cargo.toml`, or is a `use` missing?",
+		"E0412" => "Is the type imported with a `use`, or misspelled?",
+		"E0425" => "Is the item imported with a `use`, declared in this scope, or misspelled?",
+		_ => return None,
+	})
+}
+
+/// Returns the header of the table of the [`Diagnostic`](Summary::Diagnostic) items
+///
+/// A column with the crate names is added if `package_names` is not empty,
+/// and the column with the locations is omitted unless `location_column` is `true`.
+fn diagnostic_table_header(
+	package_names: &HashMap<String, String>,
+	location_column: bool,
+) -> &'static str {
+	match (package_names.is_empty(), location_column) {
+		(true, true) => "|Level|Message|Location|\n|:--|:--|--:|\n",
+		(true, false) => "|Level|Message|\n|:--|:--|\n",
+		(false, true) => "|Level|Crate|Message|Location|\n|:--|:--|:--|--:|\n",
+		(false, false) => "|Level|Crate|Message|\n|:--|:--|:--|\n",
+	}
+}
+
+/// Appends the outputs of the step to the file at `path`, as `name=value` lines
+///
+/// The outputs are the numbers of emitted annotations of each kind (`error_count`, `warning_count` and `notice_count`),
+/// and whether the job fails (`has_failures`).
+///
+/// # Errors
+/// Returns any error that occurred while writing to the file.
+fn write_github_output(path: &Path, report: &Report, failure: bool) -> io::Result<()> {
+	let mut outputs = String::new();
+	for kind in [
+		AnnotationKind::Error,
+		AnnotationKind::Warning,
+		AnnotationKind::Notice,
+	] {
+		writeln!(outputs, "{}_count={}", kind.name(), report.count(kind)).unwrap();
+	}
+	writeln!(outputs, "has_failures={failure}").unwrap();
+
+	OpenOptions::new()
+		.append(true)
+		.create(true)
+		.open(path)?
+		.write_all(outputs.as_bytes())
+}
+
+/// Default template of the totals line of the summary (see `--summary-footer-template`)
+const DEFAULT_SUMMARY_FOOTER_TEMPLATE: &str = "> **TOTAL:** {errors} :x: Errors, {warnings} :warning: Warnings, {notices} :information_source: Notices";
+
+/// Numbers of annotated diagnostics and files, as rendered by `--summary-footer-template`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AnnotationStats {
+	/// Number of errors
+	errors: usize,
+	/// Number of warnings
+	warnings: usize,
+	/// Number of notices
+	notices: usize,
+	/// Number of distinct annotated files
+	files: usize,
+}
+impl AnnotationStats {
+	/// Gathers the statistics from the number of diagnostics of each kind and the number of annotated files
+	fn new(kind_count: &HashMap<AnnotationKind, usize>, files: usize) -> Self {
+		let count = |kind| kind_count.get(&kind).copied().unwrap_or_default();
+		Self {
+			errors: count(AnnotationKind::Error),
+			warnings: count(AnnotationKind::Warning),
+			notices: count(AnnotationKind::Notice),
+			files,
+		}
+	}
+
+	/// Replaces the placeholders of `template` with the statistics
+	fn render(&self, template: &str) -> String {
+		template
+			.replace("{errors}", &self.errors.to_string())
+			.replace("{warnings}", &self.warnings.to_string())
+			.replace("{notices}", &self.notices.to_string())
+			.replace(
+				"{total}",
+				&(self.errors + self.warnings + self.notices).to_string(),
+			)
+			.replace("{files}", &self.files.to_string())
+	}
+}
+
+/// Writes the totals of the summary in the rich theme (see `--summary-theme`)
+///
+/// # Errors
+/// Returns any error that occurred while writing to `file`.
+fn write_rich_totals(
+	kind_count: &HashMap<AnnotationKind, usize>,
+	file: &mut impl IoWrite,
+) -> io::Result<()> {
+	/// Width of the longest bar of the chart
+	const BAR_WIDTH: usize = 20;
+
+	let kinds = [
+		AnnotationKind::Error,
+		AnnotationKind::Warning,
+		AnnotationKind::Notice,
+	]
+	.map(|kind| (kind, kind_count.get(&kind).copied().unwrap_or_default()));
+	let chips = kinds
+		.iter()
+		.map(|(kind, count)| format!("<span>{} <b>{count}</b> {kind:?}s</span>", kind.emoji()))
+		.collect::<Vec<_>>();
+	writeln!(file, "> {}", chips.join(" &nbsp; "))?;
+	writeln!(file)?;
+
+	let max_count = kinds
+		.iter()
+		.map(|(_kind, count)| *count)
+		.max()
+		.unwrap_or_default();
+	writeln!(file, "<details><summary>Distribution</summary>")?;
+	writeln!(file)?;
+	writeln!(file, "```")?;
+	for (kind, count) in kinds {
+		let width = (count * BAR_WIDTH).div_ceil(max_count.max(1));
+		writeln!(
+			file,
+			"{:<8} {} {count}",
+			format!("{kind:?}s"),
+			"█".repeat(width)
+		)?;
+	}
+	writeln!(file, "```")?;
+	writeln!(file)?;
+	writeln!(file, "</details>")
+}
+
+/// Row of the table of the [`Diagnostic`](Summary::Diagnostic) items
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DiagnosticRow {
+	/// Emitted kind of the diagnostic
+	kind: AnnotationKind,
+	/// The diagnostic is a lint, rather than a compile error
+	lint: bool,
+	/// File of the diagnostic, if it has a location
+	file: Option<String>,
+	/// Markdown of the row, including its trailing newline
+	markdown: String,
+}
+
+/// Writes a table of `rows` under `header`, followed by a blank line
+///
+/// # Errors
+/// Returns any error that occurred while writing to `file`.
+fn write_table<'r>(
+	file: &mut impl IoWrite,
+	header: &str,
+	rows: impl IntoIterator<Item = &'r DiagnosticRow>,
+) -> io::Result<()> {
+	file.write_all(header.as_bytes())?;
+	for row in rows {
+		file.write_all(row.markdown.as_bytes())?;
+	}
+	writeln!(file)
+}
+
+/// Writes `rows` under one section per kind, errors first, as laid out by `layout`
+///
+/// Kinds without any row are omitted.
+///
+/// # Errors
+/// Returns any error that occurred while writing to `file`.
+fn write_kind_groups(
+	file: &mut impl IoWrite,
+	header: &str,
+	rows: &[DiagnosticRow],
+	layout: SummaryLayout,
+) -> io::Result<()> {
+	for kind in [
+		AnnotationKind::Error,
+		AnnotationKind::Warning,
+		AnnotationKind::Notice,
+	] {
+		let kind_rows = rows
+			.iter()
+			.filter(|row| row.kind == kind)
+			.collect::<Vec<_>>();
+		if kind_rows.is_empty() {
+			continue;
+		}
+		if layout == SummaryLayout::KindThenFile {
+			writeln!(
+				file,
+				"<details><summary>{kind}s ({})</summary>",
+				kind_rows.len()
+			)?;
+			writeln!(file)?;
+		} else {
+			writeln!(file, "## {kind:?}s")?;
+		}
+		// Lints denied by the user are told apart from the compile errors
+		let (lints, others): (Vec<_>, Vec<_>) = kind_rows.iter().copied().partition(|row| row.lint);
+		let sections = if kind == AnnotationKind::Error && !lints.is_empty() {
+			vec![
+				(Some("Compile errors"), others),
+				(Some("Denied lints"), lints),
+			]
+		} else {
+			vec![(None, kind_rows)]
+		};
+		for (heading, section_rows) in sections {
+			if section_rows.is_empty() {
+				continue;
+			}
+			if let Some(heading) = heading {
+				writeln!(file, "### {heading}")?;
+			}
+			if layout == SummaryLayout::KindThenFile {
+				write_file_groups(file, header, section_rows)?;
+			} else {
+				write_table(file, header, section_rows)?;
+			}
+		}
+		if layout == SummaryLayout::KindThenFile {
+			writeln!(file, "</details>")?;
+			writeln!(file)?;
+		}
+	}
+	Ok(())
+}
+
+/// Writes `rows` in one collapsed table per file, ordered by path
+///
+/// The rows without a location are written last.
+///
+/// # Errors
+/// Returns any error that occurred while writing to `file`.
+fn write_file_groups<'r>(
+	file: &mut impl IoWrite,
+	header: &str,
+	rows: impl IntoIterator<Item = &'r DiagnosticRow>,
+) -> io::Result<()> {
+	let mut groups: BTreeMap<(bool, Option<&str>), Vec<&DiagnosticRow>> = BTreeMap::new();
+	for row in rows {
+		let path = row.file.as_deref();
+		groups.entry((path.is_none(), path)).or_default().push(row);
+	}
+	for ((_no_location, path), rows) in groups {
+		match path {
+			Some(path) => writeln!(
+				file,
+				"<details><summary><code>{path}</code> ({})</summary>",
+				rows.len()
+			)?,
+			None => writeln!(
+				file,
+				"<details><summary>No location ({})</summary>",
+				rows.len()
+			)?,
+		}
+		writeln!(file)?;
+		write_table(file, header, rows)?;
+		writeln!(file, "</details>")?;
+		writeln!(file)?;
+	}
+	Ok(())
+}
+
+/// Write the rendered forms of the [`Diagnostic`](Summary::Diagnostic) items, reformatted as Markdown
+///
+/// The section is collapsed by default.
+fn write_rendered_summary<'s>(
+	diagnostics: impl IntoIterator<Item = &'s Summary>,
+	file: &mut impl IoWrite,
+) -> io::Result<()> {
+	writeln!(file, "<details><summary>Rendered diagnostics</summary>")?;
+	for summary in diagnostics {
+		if let Summary::Diagnostic {
+			rendered: Some(rendered),
+			..
+		} = summary
+		{
+			writeln!(file)?;
+			file.write_all(reformat_rendered(rendered).as_bytes())?;
+		}
+	}
+	writeln!(file)?;
+	writeln!(file, "</details>")
+}
+
+/// Number of lines of Cargo's standard error shown by [`write_build_output_summary`]
+const BUILD_OUTPUT_TAIL_LINES: usize = 50;
+
+/// Write the tail of Cargo's standard error, in a collapsed section
+fn write_build_output_summary(stderr: &[u8], file: &mut impl IoWrite) -> io::Result<()> {
+	let stderr = String::from_utf8_lossy(stderr);
+	let lines = stderr.lines().collect::<Vec<_>>();
+	let tail = &lines[lines.len().saturating_sub(BUILD_OUTPUT_TAIL_LINES)..];
+
+	writeln!(file, "<details><summary>Build output</summary>")?;
+	writeln!(file)?;
+	writeln!(file, "```")?;
+	for line in tail {
+		writeln!(file, "{line}")?;
+	}
+	writeln!(file, "```")?;
+	writeln!(file)?;
+	writeln!(file, "</details>")
+}
+
+/// Reformats a diagnostic rendered by rustc as Markdown
+///
+/// The message line is followed by the location, the source snippets are code-fenced,
+/// and the notes and helps are listed as bullets.
+fn reformat_rendered(rendered: &str) -> String {
+	let mut lines = rendered.lines();
+	let mut markdown = String::new();
+	writeln!(markdown, "{}", lines.next().unwrap_or_default()).unwrap();
+
+	let mut snippet: Vec<&str> = Vec::new();
+	let mut bullets = false;
+	let flush_snippet = |markdown: &mut String, snippet: &mut Vec<&str>| {
+		while snippet.last().is_some_and(|line| line.trim().is_empty()) {
+			snippet.pop();
+		}
+		if !snippet.is_empty() {
+			writeln!(markdown, "\n```\n{}\n```", snippet.join("\n")).unwrap();
+			snippet.clear();
+		}
+	};
+	for line in lines {
+		let trimmed = line.trim();
+		let code = line.split_once('|').and_then(|(gutter, code)| {
+			gutter
+				.trim()
+				.bytes()
+				.all(|b| b.is_ascii_digit())
+				.then(|| code.strip_prefix(' ').unwrap_or(code))
+		});
+		if let Some(code) = code.or_else(|| (trimmed == "...").then_some(trimmed)) {
+			if !snippet.is_empty() || !code.trim().is_empty() {
+				snippet.push(code);
+			}
+			bullets = false;
+			continue;
+		}
+		flush_snippet(&mut markdown, &mut snippet);
+		if trimmed.is_empty() {
+			continue;
+		}
+		if let Some(location) = trimmed
+			.strip_prefix("--> ")
+			.or_else(|| trimmed.strip_prefix("::: "))
+		{
+			writeln!(markdown, "\n`{location}`").unwrap();
+			bullets = false;
+		} else {
+			if !bullets {
+				markdown.push('\n');
+				bullets = true;
+			}
+			writeln!(
+				markdown,
+				"- {}",
+				trimmed.strip_prefix("= ").unwrap_or(trimmed)
+			)
+			.unwrap();
+		}
+	}
+	flush_snippet(&mut markdown, &mut snippet);
+	markdown
+}
+
+/// Write a summary of the [`Test`](Summary::Test) items
+fn write_test_summary<'s>(
+	tests: impl IntoIterator<Item = &'s Summary>,
+	location_format: LocationFormat,
+	file: &mut impl IoWrite,
+) -> io::Result<()> {
+	writeln!(file, "# Failed tests")?;
+	writeln!(file, "|Test|Location|")?;
+	writeln!(file, "|:--|--:|")?;
+	for summary in tests {
+		let Summary::Test { name, location } = summary else {
+			unreachable!()
+		};
+		let location = location
+			.as_ref()
+			.map(|location| location_format.format(location))
+			.unwrap_or_default();
+		writeln!(file, "|`{name}`|{location}|")?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cli() {
+		Cli::command().debug_assert();
+	}
+
+	fn parse_cli(args: &[&str]) -> Cli {
+		Cli::parse_from(["cargo-ghannotate", "--cargo=cargo"].iter().chain(args))
+	}
+
+	fn compiler_message(package_id: &str, level: &str, file_name: &str, line: usize) -> String {
+		serde_json::json!({
+			"reason": "compiler-message",
+			"package_id": package_id,
+			"message": {
+				"message": format!("{level} in {package_id}"),
+				"level": level,
+				"spans": [{
+					"file_name": file_name,
+					"line_start": line,
+					"line_end": line,
+					"column_start": 1,
+					"column_end": 2,
+					"is_primary": true,
+				}],
+				"rendered": null,
+			},
+		})
+		.to_string()
+	}
+
+	/// Temporary directory containing a fake `cargo` executable, removed when dropped
+	#[cfg(unix)]
+	struct FakeCargo {
+		/// Temporary directory
+		dir: PathBuf,
+		/// Path of the fake `cargo` executable
+		path: PathBuf,
+	}
+	#[cfg(unix)]
+	impl Drop for FakeCargo {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.dir);
+		}
+	}
+
+	/// Creates a fake `cargo` executing the shell `script` in a new temporary directory
+	///
+	/// The script can refer to its directory as `$(dirname "$0")`.
+	#[cfg(unix)]
+	fn fake_cargo(script: &str) -> FakeCargo {
+		use std::{
+			os::unix::fs::PermissionsExt,
+			sync::atomic::{AtomicUsize, Ordering},
+		};
+
+		static COUNT: AtomicUsize = AtomicUsize::new(0);
+		let dir = std::env::temp_dir().join(format!(
+			"ghannotate-cargo-{}-{}",
+			std::process::id(),
+			COUNT.fetch_add(1, Ordering::Relaxed)
+		));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("cargo");
+		let cargo = FakeCargo { dir, path };
+		std::fs::write(&cargo.path, format!("#!/bin/sh\n{script}")).unwrap();
+		std::fs::set_permissions(&cargo.path, std::fs::Permissions::from_mode(0o755)).unwrap();
+		cargo
+	}
+
+	fn with_code(compiler_message: &str, code: &str) -> String {
+		let mut message: serde_json::Value = serde_json::from_str(compiler_message).unwrap();
+		message["message"]["code"] = serde_json::json!({ "code": code });
+		message.to_string()
+	}
+
+	#[test]
+	fn keep_going_errors_fail() {
+		let cli = parse_cli(&["--allow-warnings", "check"]);
+		let input = [
+			compiler_message("a", "error", "a/src/lib.rs", 1),
+			compiler_message("b", "warning", "b/src/lib.rs", 1),
+			compiler_message("b", "warning", "b/src/lib.rs", 2),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert_eq!(report.max_annotation, AnnotationKind::Error);
+		assert!(cli.should_fail(&report, false, false));
+	}
+
+	#[test]
+	fn keep_going_warnings_pass() {
+		let cli = parse_cli(&["--allow-warnings", "check"]);
+		let input = [
+			compiler_message("a", "warning", "a/src/lib.rs", 1),
+			compiler_message("b", "warning", "b/src/lib.rs", 1),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert_eq!(report.max_annotation, AnnotationKind::Warning);
+		assert!(!cli.should_fail(&report, true, false));
+	}
+
+	#[test]
+	fn output_format_azure() {
+		let cli = parse_cli(&["--output-format=azure", "check"]);
+		let input = compiler_message("a", "warning", "src/lib.rs", 4);
+
+		let mut out = Vec::new();
+		cli.process(input.as_bytes(), &mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"##vso[task.logissue type=warning;sourcepath=src/lib.rs;linenumber=4;columnnumber=1]warning in a\n"
+		);
+	}
+
+	#[test]
+	fn summary_crate_names() {
+		let cli = parse_cli(&["--cargo-metadata", "check"]);
+		let metadata = serde_json::json!({
+			"packages": [{
+				"name": "friendly",
+				"id": "path+file:///work/friendly#0.1.0",
+			}],
+		})
+		.to_string();
+		let package_names = package_names(metadata.as_bytes()).unwrap();
+		let input = compiler_message(
+			"path+file:///work/friendly#0.1.0",
+			"warning",
+			"src/lib.rs",
+			1,
+		);
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &package_names, &mut summary)
+			.unwrap();
+		assert!(String::from_utf8(summary)
+			.unwrap()
+			.contains("|friendly|warning in path+file:///work/friendly#0.1.0|"));
+	}
+
+	#[test]
+	fn input_format_json_array() {
+		let messages = [
+			compiler_message("a", "error", "a/src/lib.rs", 1),
+			compiler_message("b", "warning", "b/src/lib.rs", 2),
+		];
+		let ndjson = messages.join("\n");
+		let array = serde_json::to_string_pretty(
+			&messages
+				.iter()
+				.map(|message| serde_json::from_str(message).unwrap())
+				.collect::<Vec<serde_json::Value>>(),
+		)
+		.unwrap();
+		// Text outside of the messages, including braces in strings of the messages
+		let log = format!(
+			"Compiling a\n{}\nnot JSON ] ,\n[{}]\ntrailing {{",
+			messages[0].replace("error in a", "error in a }"),
+			messages[1]
+		);
+
+		let ndjson_report = parse_cli(&["check"])
+			.process(ndjson.as_bytes(), io::sink())
+			.unwrap();
+		let array_report = parse_cli(&["--input-format=json", "check"])
+			.process(array.as_bytes(), io::sink())
+			.unwrap();
+		assert_eq!(ndjson_report.annotations.len(), 2);
+		assert_eq!(ndjson_report.annotations, array_report.annotations);
+
+		let mut out = Vec::new();
+		let log_report = parse_cli(&["--input-format=json", "check"])
+			.process(log.as_bytes(), &mut out)
+			.unwrap();
+		assert_eq!(log_report.annotations.len(), 2);
+		assert!(String::from_utf8(out).unwrap().contains("::error in a }"));
+
+		for alias in ["ndjson", "json-lines"] {
+			let cli = parse_cli(&[&format!("--input-format={alias}"), "check"]);
+			assert_eq!(cli.options.input_format, InputFormat::CargoJson);
+		}
+	}
+
+	#[test]
+	fn strip_span_prefix() {
+		let cli = parse_cli(&[
+			"--strip-span-prefix=/other/",
+			"--strip-span-prefix=/build/hash/",
+			"--strip-span-prefix=/build/",
+			"check",
+		]);
+		let input = compiler_message("a", "warning", "/build/hash/src/lib.rs", 1);
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert_eq!(
+			report.annotations.first().unwrap().file.as_ref(),
+			"src/lib.rs"
+		);
+	}
+
+	#[test]
+	fn trailing_garbage() {
+		let cli = parse_cli(&["check"]);
+		let input = compiler_message("a", "warning", "src/lib.rs", 1) + " PASSED";
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert_eq!(report.annotations.len(), 1);
+	}
+
+	#[test]
+	fn annotate_test_output() {
+		let cli = parse_cli(&["--annotate-test-output", "test"]);
+		let input = serde_json::json!({
+			"type": "test",
+			"event": "failed",
+			"name": "tests::it_works",
+			"stdout": "printed by the test\n\
+				thread 'tests::it_works' panicked at src/lib.rs:12:9:\n\
+				assertion failed: 100% wrong\n",
+		})
+		.to_string();
+
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		assert_eq!(report.max_annotation, AnnotationKind::Error);
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"::error file=src/lib.rs,line=12,col=9,title=test tests%3A%3Ait_works failed::\
+			printed by the test%0A\
+			thread 'tests::it_works' panicked at src/lib.rs:12:9:%0A\
+			assertion failed: 100%25 wrong\n"
+		);
+	}
+
+	#[test]
+	fn duration() {
+		assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+		assert_eq!(parse_duration("1h30s"), Ok(Duration::from_secs(3630)));
+		assert!(parse_duration("10").is_err());
+		assert!(parse_duration("10y").is_err());
+	}
+
+	#[test]
+	fn changed_within() {
+		let dir = std::env::temp_dir().join(format!("ghannotate-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let old = dir.join("old.rs");
+		let new = dir.join("new.rs");
+		File::create(&old)
+			.unwrap()
+			.set_modified(SystemTime::now() - Duration::from_secs(60 * 60))
+			.unwrap();
+		File::create(&new).unwrap();
+
+		let cli = parse_cli(&["--changed-within=10m", "check"]);
+		let input = [
+			compiler_message("a", "warning", old.to_str().unwrap(), 1),
+			compiler_message("a", "warning", new.to_str().unwrap(), 1),
+			compiler_message("a", "warning", dir.join("missing.rs").to_str().unwrap(), 1),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(report.annotations.len(), 1);
+		assert_eq!(
+			report.annotations.first().unwrap().file.as_ref(),
+			new.to_str().unwrap()
+		);
+	}
+
+	#[test]
+	fn summary_max_message_chars() {
+		let cli = parse_cli(&["--summary-max-message-chars=10", "check"]);
+		let input = compiler_message("a_long_package_name", "warning", "src/lib.rs", 1);
+
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		assert!(String::from_utf8(summary).unwrap().contains("|warning i…|"));
+		assert!(String::from_utf8(out)
+			.unwrap()
+			.ends_with("::warning in a_long_package_name\n"));
+	}
+
+	#[test]
+	fn annotations_iterator() {
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			"Compiling a v0.1.0".to_owned(),
+			compiler_message("a", "error", "src/lib.rs", 2),
+			compiler_message("a", "error", "src/lib.rs", 0),
+		]
+		.join("\n");
+
+		let annotations = annotations(input.as_bytes(), &Options::default()).collect::<Vec<_>>();
+		assert_eq!(annotations.len(), 3);
+		assert!(matches!(
+			&annotations[0],
+			Ok(Message::Annotation(annotation, _)) if annotation.kind == AnnotationKind::Warning
+		));
+		assert!(matches!(
+			&annotations[1],
+			Ok(Message::Annotation(annotation, _)) if annotation.kind == AnnotationKind::Error
+		));
+		assert!(matches!(
+			annotations[2],
+			Err(ProcessError::Invalid(AnnotationError::NullLine))
+		));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn cargo_metadata_failure() {
+		let cargo = fake_cargo("echo 'error: could not find `Cargo.toml`' >&2\nexit 101\n");
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--cargo-metadata",
+			"--input=-",
+		]);
+		let err = cli.invoke_cargo_metadata().unwrap_err();
+		assert!(err.to_string().starts_with("`cargo metadata` failed"));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn cargo_timeout() {
+		let cargo = fake_cargo("sleep 30 &\nsleep 30\n");
+
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--cargo-timeout=200ms",
+			"check",
+		]);
+		let start = Instant::now();
+		let (report, cargo_success, timed_out) = cli.run(io::sink()).unwrap();
+		assert!(start.elapsed() < Duration::from_secs(10));
+		assert!(report.annotations.is_empty());
+		assert!(!cargo_success);
+		assert!(timed_out);
+
+		let mut out = Vec::new();
+		cli.emit_timeout_error(&mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"::error::Cargo was killed after running for 200ms\n"
+		);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn cargo_timeout_fails() {
+		let error = compiler_message("a", "error", "src/lib.rs", 1);
+		let cargo = fake_cargo(&format!("echo '{error}'\nsleep 30\n"));
+
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--cargo-timeout=300ms",
+			"--max-errors=5",
+			"check",
+		]);
+		let (report, cargo_success, timed_out) = cli.run(io::sink()).unwrap();
+		assert!(timed_out);
+		assert_eq!(report.count(AnnotationKind::Error), 1);
+		assert!(cli.should_fail(&report, cargo_success, timed_out));
+
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--cargo-timeout=300ms",
+			"--downgrade-errors-to=notice",
+			"check",
+		]);
+		let (report, cargo_success, timed_out) = cli.run(io::sink()).unwrap();
+		assert!(cli.should_fail(&report, cargo_success, timed_out));
+
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--cargo-timeout=300ms",
+			"--max-errors=5",
+			"--exit-zero-on-no-diagnostics-even-if-cargo-fails",
+			"check",
+		]);
+		let (report, cargo_success, timed_out) = cli.run(io::sink()).unwrap();
+		assert!(!cli.should_fail(&report, cargo_success, timed_out));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn streams_cargo_output() {
+		/// Creates the file at its path once anything is written to it
+		struct Touch(PathBuf);
+		impl IoWrite for Touch {
+			fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+				File::create(&self.0)?;
+				Ok(buf.len())
+			}
+
+			fn flush(&mut self) -> io::Result<()> {
+				Ok(())
+			}
+		}
+
+		let error = compiler_message("a", "error", "src/lib.rs", 1);
+		let cargo = fake_cargo(&format!(
+			"echo '{error}'\nwhile [ ! -e \"$(dirname \"$0\")/annotated\" ]; do sleep 0.05; done\n"
+		));
+
+		// Cargo only exits once the error has been annotated
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--cargo-timeout=10s",
+			"check",
+		]);
+		let (report, cargo_success, timed_out) =
+			cli.run(Touch(cargo.dir.join("annotated"))).unwrap();
+		assert!(!timed_out);
+		assert!(cargo_success);
+		assert_eq!(report.count(AnnotationKind::Error), 1);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn multi() {
+		let unused = compiler_message("a", "warning", "src/lib.rs", 1);
+		let lint = compiler_message("a", "warning", "src/lib.rs", 2);
+		let cargo = fake_cargo(&format!(
+			"[ \"$3\" = --all-targets ] || exit 2\n\
+			echo '{unused}'\n\
+			[ \"$1\" = clippy ] && echo '{lint}'\n\
+			exit 0\n"
+		));
+
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"multi",
+			"check",
+			"clippy",
+			"--",
+			"--all-targets",
+		]);
+		let Some(CliCommand::Multi(multi)) = &cli.command else {
+			panic!("`multi` is not parsed");
+		};
+		let mut out = Vec::new();
+		let (report, success, timed_out) = cli.process_multi(multi, &mut out).unwrap();
+		assert!(success);
+		assert!(!timed_out);
+		assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+		assert_eq!(report.annotations.len(), 2);
+		let messages = report
+			.summaries
+			.iter()
+			.map(|summary| match summary {
+				Summary::Diagnostic { message, .. } => message.as_str(),
+				Summary::Test { name, .. } => name.as_str(),
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			messages,
+			["`check`: warning in a", "`clippy`: warning in a"]
+		);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn capture_stderr() {
+		let warning = compiler_message("a", "warning", "src/lib.rs", 1);
+		let cargo = fake_cargo(&format!(
+			"echo '{warning}'
+			for i in $(seq 1 60); do echo \"line $i\" >&2; done
+			echo 'error: linking with `cc` failed' >&2
+			exit 101
+"
+		));
+
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--capture-stderr",
+			"multi",
+			"build",
+		]);
+		let Some(CliCommand::Multi(multi)) = &cli.command else {
+			panic!("`multi` is not parsed");
+		};
+		let (report, success, _) = cli.process_multi(multi, Vec::new()).unwrap();
+		assert!(!success);
+
+		let summary = String::from_utf8(cli.summary(&report, &HashMap::new()).unwrap()).unwrap();
+		let output = summary
+			.split_once("<details><summary>Build output</summary>\n\n```\n")
+			.unwrap()
+			.1;
+		assert!(output.starts_with("line 12\n"));
+		assert!(output.ends_with("error: linking with `cc` failed\n```\n\n</details>\n"));
+	}
+
+	#[test]
+	fn input_glob() {
+		let dir = std::env::temp_dir().join(format!("ghannotate-glob-{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("linux")).unwrap();
+		std::fs::create_dir_all(dir.join("macos")).unwrap();
+		// Each runner checks out the workspace at its own location
+		let linux = "path+file:///home/runner/work/r/r#a@0.1.0";
+		let macos = "path+file:///Users/runner/work/r/r#a@0.1.0";
+		std::fs::write(
+			dir.join("linux/cargo.json"),
+			[
+				compiler_message(linux, "warning", "src/lib.rs", 1),
+				compiler_message(linux, "error", "src/unix.rs", 2),
+			]
+			.join("\n"),
+		)
+		.unwrap();
+		std::fs::write(
+			dir.join("macos/cargo.json"),
+			[
+				compiler_message(macos, "warning", "src/lib.rs", 1),
+				compiler_message(macos, "warning", "src/macos.rs", 3),
+			]
+			.join("\n"),
+		)
+		.unwrap();
+		// Invalid UTF-8, whose error must not prevent the other files from being read
+		std::fs::write(dir.join("broken.json"), b"\xFF\xFE\n").unwrap();
+		std::fs::write(
+			dir.join("linux/cargo.log"),
+			compiler_message("a", "error", "src/log.rs", 4),
+		)
+		.unwrap();
+
+		let cli = parse_cli(&[&format!("--input-glob={}/**/*.json", dir.display())]);
+		let mut out = Vec::new();
+		let (report, success, timed_out) = cli.run(&mut out).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert!(success);
+		assert!(!timed_out);
+		let files = report
+			.annotations
+			.iter()
+			.map(|annotation| annotation.file.as_ref())
+			.collect::<Vec<_>>();
+		assert_eq!(files, ["src/lib.rs", "src/macos.rs", "src/unix.rs"]);
+		assert_eq!(report.summaries.len(), 3);
+		assert_eq!(String::from_utf8(out).unwrap().lines().count(), 3);
+
+		assert!(
+			Cli::try_parse_from(["cargo-ghannotate", "--input=-", "--input-glob=*.json"]).is_err()
+		);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn watch() {
+		let warning = compiler_message("a", "warning", "src/lib.rs", 1);
+		let cargo = fake_cargo(&format!(
+			"echo run >> \"$(dirname \"$0\")/runs\"\n\
+			echo '{warning}'\n"
+		));
+		let summary = cargo.dir.join("SUMMARY.md");
+
+		let cli = Cli::parse_from([
+			"cargo-ghannotate",
+			&format!("--cargo={}", cargo.path.display()),
+			"--watch",
+			"--output-format=human",
+			"check",
+		]);
+		// A single simulated change, after which watching stops
+		let mut changes = [true, false].into_iter();
+		let mut out = Vec::new();
+		let count = cli
+			.watch(
+				|| Ok(changes.next().unwrap()),
+				Some(&summary),
+				&HashMap::new(),
+				&mut out,
+			)
+			.unwrap();
+		let invocations = std::fs::read_to_string(cargo.dir.join("runs")).unwrap();
+		let summary = std::fs::read_to_string(&summary).unwrap();
+		assert_eq!(count, 2);
+		assert_eq!(invocations, "run\nrun\n");
+		let out = String::from_utf8(out).unwrap();
+		assert_eq!(out.matches("\x1b[2J").count(), 2);
+		assert_eq!(out.matches("src/lib.rs:1:1: warning: ").count(), 2);
+		assert!(summary.contains("warning in a"));
+
+		assert!(
+			Cli::try_parse_from(["cargo-ghannotate", "--watch", "--input=-", "check"]).is_err()
+		);
+	}
+
+	#[test]
+	fn source_watcher() {
+		let dir = std::env::temp_dir().join(format!("ghannotate-sources-{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("src")).unwrap();
+		std::fs::create_dir_all(dir.join("target")).unwrap();
+		std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+		let mut watcher = SourceWatcher::new(dir.clone()).unwrap();
+		watcher.poll_interval = Duration::from_millis(10);
+		watcher.debounce = Duration::from_millis(100);
+		assert_eq!(watcher.snapshot.len(), 1);
+
+		let writer = thread::spawn({
+			let dir = dir.clone();
+			move || {
+				thread::sleep(Duration::from_millis(50));
+				std::fs::write(dir.join("target/build.rs"), "").unwrap();
+				std::fs::write(dir.join("src/a.rs"), "").unwrap();
+				thread::sleep(Duration::from_millis(20));
+				std::fs::write(dir.join("src/b.rs"), "").unwrap();
+			}
+		});
+		watcher.wait().unwrap();
+		writer.join().unwrap();
+		let changed = watcher.poll().unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		// Both files were reported by a single change
+		assert_eq!(watcher.snapshot.len(), 3);
+		assert!(!changed);
+	}
+
+	#[test]
+	fn summary_split_by_kind() {
+		let cli = parse_cli(&["--summary-split-by-kind", "check"]);
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "error", "src/lib.rs", 2),
+			compiler_message("a", "warning", "src/lib.rs", 3),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		let errors = summary.find("## Errors\n").unwrap();
+		let warnings = summary.find("## Warnings\n").unwrap();
+		assert!(errors < warnings);
+		assert!(!summary.contains("## Notices"));
+		assert_eq!(summary.matches("|Level|Message|Location|").count(), 2);
+	}
+
+	#[test]
+	fn summary_layout_kind_then_file() {
+		let cli = parse_cli(&["--summary-layout=kind-then-file", "check"]);
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "error", "src/lib.rs", 2),
+			compiler_message("a", "warning", "src/main.rs", 3),
+			compiler_message("a", "warning", "src/lib.rs", 4),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		assert!(!summary.contains("Notices ("));
+		let (errors, warnings) = summary
+			.split_once("<details><summary>:warning: Warnings (3)</summary>\n")
+			.unwrap();
+		let errors = errors
+			.split_once("<details><summary>:x: Errors (1)</summary>\n")
+			.unwrap()
+			.1;
+		assert_eq!(errors.matches("<details>").count(), 1);
+		assert!(errors.contains("<details><summary><code>src/lib.rs</code> (1)</summary>\n"));
+		assert_eq!(errors.matches("</details>").count(), 2);
+		let lib = warnings
+			.find("<details><summary><code>src/lib.rs</code> (2)</summary>\n")
+			.unwrap();
+		let main = warnings
+			.find("<details><summary><code>src/main.rs</code> (1)</summary>\n")
+			.unwrap();
+		assert!(lib < main);
+		assert_eq!(warnings.matches("|Level|Message|Location|").count(), 2);
+		assert_eq!(warnings.matches("</details>").count(), 3);
+
+		assert!(Cli::try_parse_from([
+			"cargo-ghannotate",
+			"--summary-split-by-kind",
+			"--summary-layout=by-file",
+			"check"
+		])
+		.is_err());
+	}
+
+	#[test]
+	fn summary_no_location_column() {
+		let cli = parse_cli(&["--summary-no-location-column", "check"]);
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "error", "src/main.rs", 2),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		let table = summary
+			.lines()
+			.filter(|line| line.starts_with('|'))
+			.collect::<Vec<_>>();
+		assert_eq!(table.len(), 4);
+		assert_eq!(table[0], "|Level|Message|");
+		assert_eq!(table[1], "|:--|:--|");
+		for line in table {
+			assert_eq!(line.matches('|').count(), 3);
+		}
+		assert!(!summary.contains("src/lib.rs"));
+		assert!(!summary.contains("src/main.rs"));
+	}
+
+	#[test]
+	fn summary_hints() {
+		let input = [
+			with_code(&compiler_message("a", "error", "src/lib.rs", 1), "E0432"),
+			with_code(&compiler_message("a", "error", "src/lib.rs", 2), "E0308"),
+		]
+		.join("\n");
+		let summary = |cli: &Cli| {
+			let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+			let mut summary = Vec::new();
+			cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+				.unwrap();
+			String::from_utf8(summary).unwrap()
+		};
+
+		let hinted = summary(&parse_cli(&["--summary-hints", "check"]));
+		assert_eq!(hinted.matches(":bulb:").count(), 1);
+		assert!(hinted.contains(
+			"|error in a<br>:bulb: Is the crate added to `Cargo.toml`, and is the path of the `use` right?|`src/lib.rs:1`|"
+		));
+		assert!(!summary(&parse_cli(&["check"])).contains(":bulb:"));
+	}
+
+	#[test]
+	fn summary_denied_lints() {
+		let cli = parse_cli(&["--summary-split-by-kind", "check"]);
+		let input = [
+			with_code(
+				&compiler_message("a", "error", "src/lib.rs", 1),
+				"clippy::needless_return",
+			),
+			with_code(&compiler_message("a", "error", "src/lib.rs", 2), "E0599"),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert!(cli.should_fail(&report, false, false));
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		let (compile_errors, denied_lints) = summary.split_once("### Denied lints\n").unwrap();
+		assert!(compile_errors.contains("### Compile errors\n"));
+		assert!(compile_errors.contains("`src/lib.rs:2`"));
+		assert!(!compile_errors.contains("`src/lib.rs:1`"));
+		assert!(denied_lints.contains("`src/lib.rs:1`"));
+
+		let cli = parse_cli(&["--summary-layout=kind-then-file", "check"]);
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		let (compile_errors, denied_lints) = summary.split_once("### Denied lints\n").unwrap();
+		assert!(compile_errors.contains("### Compile errors\n"));
+		assert!(compile_errors.contains("`src/lib.rs:2`"));
+		assert!(!compile_errors.contains("`src/lib.rs:1`"));
+		assert!(denied_lints.contains("`src/lib.rs:1`"));
+
+		let cli = parse_cli(&["check"]);
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		assert!(summary.contains("|:x: Error (denied lint)|error in a|`src/lib.rs:1`|\n"));
+		assert!(summary.contains("|:x: Error|error in a|`src/lib.rs:2`|\n"));
+	}
+
+	#[test]
+	fn github_output() {
+		let path = std::env::temp_dir().join(format!("ghannotate-output-{}", std::process::id()));
+		std::fs::write(&path, "previous=output\n").unwrap();
+		let cli = parse_cli(&["--write-github-output", "check"]);
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "error", "src/lib.rs", 2),
+			compiler_message("a", "warning", "src/lib.rs", 3),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		write_github_output(&path, &report, cli.should_fail(&report, false, false)).unwrap();
+		let outputs = std::fs::read_to_string(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(
+			outputs,
+			"previous=output\nerror_count=1\nwarning_count=2\nnotice_count=0\nhas_failures=true\n"
+		);
+	}
+
+	#[test]
+	fn empty_file_name() {
+		let cli = parse_cli(&["check"]);
+		let mut other: serde_json::Value =
+			serde_json::from_str(&compiler_message("a", "warning", "", 1)).unwrap();
+		other["message"]["message"] = serde_json::json!("other warning");
+		let input = [
+			compiler_message("a", "warning", "", 1),
+			other.to_string(),
+			compiler_message("a", "warning", "", 1),
+		]
+		.join("\n");
+
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"::warning::warning in a\n::warning::other warning\n"
+		);
+		assert!(matches!(
+			report.summaries[..],
+			[
+				Summary::Diagnostic { location: None, .. },
+				Summary::Diagnostic { location: None, .. }
+			]
+		));
+	}
+
+	#[test]
+	fn summary_rich_theme() {
+		let cli = parse_cli(&["--summary-theme=rich", "check"]);
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "error", "src/lib.rs", 2),
+			compiler_message("a", "warning", "src/lib.rs", 3),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		assert!(summary.contains("<span>:x: <b>1</b> Errors</span>"));
+		assert!(summary.contains("<span>:warning: <b>2</b> Warnings</span>"));
+		assert!(summary.contains("<span>:information_source: <b>0</b> Notices</span>"));
+		assert!(summary.contains(&format!("Warnings {} 2\n", "█".repeat(20))));
+		assert!(summary.contains(&format!("Errors   {} 1\n", "█".repeat(10))));
+		assert!(!summary.contains("**TOTAL:**"));
+	}
+
+	#[test]
+	fn annotation_prefix() {
+		let cli = parse_cli(&["--annotation-prefix=clippy", "check"]);
+		let input = compiler_message("a", "warning", "src/lib.rs", 1);
+
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		assert!(String::from_utf8(out)
+			.unwrap()
+			.contains(",title=[clippy] warning in a::"));
+
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		assert!(String::from_utf8(summary)
+			.unwrap()
+			.contains("|[clippy] warning in a|"));
+	}
+
+	#[test]
+	fn downgrade_errors() {
+		let cli = parse_cli(&["--downgrade-errors-to=warning", "--allow-warnings", "check"]);
+		let input = compiler_message("a", "error", "src/lib.rs", 1);
+
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		assert!(String::from_utf8(out).unwrap().starts_with("::warning "));
+		assert!(!cli.should_fail(&report, false, false));
+
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		assert!(String::from_utf8(summary)
+			.unwrap()
+			.contains("|:warning: Warning (originally Error)|"));
+	}
+
+	#[test]
+	fn unknown_level() {
+		let input = [
+			compiler_message("a", "future-error", "src/lib.rs", 1),
+			compiler_message("a", "warning", "src/lib.rs", 2),
+		]
+		.join("\n");
+
+		let cli = parse_cli(&["check"]);
+		let report = cli.process(input.as_bytes(), Vec::new()).unwrap();
+		assert_eq!(report.annotations.len(), 2);
+		assert_eq!(report.count(AnnotationKind::Notice), 1);
+		assert!(matches!(
+			report.summaries[0],
+			Summary::Diagnostic {
+				level: DiagnosticLevel::Unknown,
+				..
+			}
+		));
+	}
+
+	#[test]
+	fn map_level() {
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "note", "src/lib.rs", 2),
+		]
+		.join("\n");
+
+		let cli = parse_cli(&[
+			"--map-level=warning=error",
+			"--map-level",
+			"note=warning",
+			"--allow-warnings",
+			"check",
+		]);
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		assert!(out.lines().next().unwrap().starts_with("::error "));
+		assert!(out.lines().nth(1).unwrap().starts_with("::warning "));
+		assert_eq!(report.max_annotation, AnnotationKind::Error);
+		assert!(cli.should_fail(&report, true, false));
+
+		let cli = parse_cli(&["--map-level=warning=notice", "check"]);
+		let report = cli.process(input.as_bytes(), Vec::new()).unwrap();
+		assert_eq!(report.max_annotation, AnnotationKind::Notice);
+		assert!(!cli.should_fail(&report, true, false));
+
+		let cli = parse_cli(&["--map-level=error=notice", "check"]);
+		let input = compiler_message("a", "error", "src/lib.rs", 1);
+		let report = cli.process(input.as_bytes(), Vec::new()).unwrap();
+		assert_eq!(report.hidden_errors, 1);
+		assert!(!cli.should_fail(&report, false, false));
+
+		assert!(
+			Cli::try_parse_from(["cargo-ghannotate", "--map-level=lint=error", "check"]).is_err()
+		);
+		assert!(
+			Cli::try_parse_from(["cargo-ghannotate", "--map-level=warning=fatal", "check"])
+				.is_err()
+		);
+		assert!(Cli::try_parse_from(["cargo-ghannotate", "--map-level=warning", "check"]).is_err());
+	}
+
+	#[test]
+	fn annotation_file_exists_check() {
+		let root = std::env::temp_dir().join(format!("ghannotate-exists-{}", std::process::id()));
+		std::fs::create_dir_all(root.join("src")).unwrap();
+		std::fs::write(root.join("src/lib.rs"), "").unwrap();
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "warning", "crates/a/src/lib.rs", 1),
+		]
+		.join("\n");
+		let report = parse_cli(&["check"])
+			.process(input.as_bytes(), io::sink())
+			.unwrap();
+		let notes = ["src/lib.rs", "crates/a/src/lib.rs"].map(|file| {
+			let annotation = report
+				.annotations
+				.iter()
+				.find(|annotation| annotation.file == file);
+			missing_file_note(annotation.unwrap(), &root)
+		});
+		std::fs::remove_dir_all(&root).unwrap();
+		assert!(notes[0].is_none());
+		assert!(notes[1]
+			.as_deref()
+			.unwrap()
+			.starts_with("the annotated file `crates/a/src/lib.rs` does not exist"));
+
+		assert!(Cli::try_parse_from([
+			"cargo-ghannotate",
+			"--annotation-file-exists-check",
+			"check"
+		])
+		.is_err());
+		assert!(
+			parse_cli(&["--annotation-file-exists-check", "--verbose", "check"])
+				.annotation_file_exists_check
+		);
+	}
+
+	#[test]
+	fn summary_footer_template() {
+		let input = [
+			compiler_message("a", "error", "src/lib.rs", 1),
+			compiler_message("a", "warning", "src/lib.rs", 2),
+			compiler_message("a", "error", "src/main.rs", 3),
+		]
+		.join("\n");
+
+		let cli = parse_cli(&["check"]);
+		let report = cli.process(input.as_bytes(), Vec::new()).unwrap();
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		assert!(String::from_utf8(summary).unwrap().contains(
+			"> **TOTAL:** 2 :x: Errors, 1 :warning: Warnings, 0 :information_source: Notices\n"
+		));
+
+		let cli = parse_cli(&[
+			"--summary-footer-template",
+			"{errors} errors in {files} files ({total} in total), see [the dashboard](https://example.com)",
+			"check",
+		]);
+		let mut summary = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+			.unwrap();
+		assert!(String::from_utf8(summary).unwrap().contains(
+			"\n2 errors in 2 files (3 in total), see [the dashboard](https://example.com)\n"
+		));
+	}
+
+	#[test]
+	fn ignore_file() {
+		let path = std::env::temp_dir().join(format!("ghannotate-ignore-{}", std::process::id()));
+		std::fs::write(&path, "# Known issues\nsrc/**/*.rs:2\n").unwrap();
+		let cli = parse_cli(&[&format!("--ignore-file={}", path.display()), "check"]);
+		std::fs::remove_file(&path).unwrap();
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "error", "src/bin/main.rs", 2),
+			compiler_message("a", "warning", "tests/it.rs", 2),
+		]
+		.join("\n");
+
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		assert_eq!(report.annotations.len(), 2);
+		assert_eq!(report.ignored.len(), 1);
+		assert_eq!(report.max_annotation, AnnotationKind::Warning);
+		assert!(!String::from_utf8(out).unwrap().contains("src/bin/main.rs"));
+
+		let mut summary = Vec::new();
+		cli.write_ignored_summary(&report.ignored, &HashMap::new(), &mut summary)
+			.unwrap();
+		let summary = String::from_utf8(summary).unwrap();
+		assert!(summary.starts_with("<details><summary>Known issues (ignored)</summary>"));
+		assert!(summary.contains("`src/bin/main.rs:2`"));
+	}
+
+	#[test]
+	fn normalize_path_case() {
+		let input = [
+			compiler_message("a", "warning", "src/Foo.rs", 1),
+			compiler_message("a", "warning", "src/foo.rs", 1),
+		]
+		.join("\n");
+
+		let report = parse_cli(&["check"])
+			.process(input.as_bytes(), io::sink())
+			.unwrap();
+		assert_eq!(report.annotations.len(), 2);
+
+		let report = parse_cli(&["--normalize-path-case", "check"])
+			.process(input.as_bytes(), io::sink())
+			.unwrap();
+		assert_eq!(report.annotations.len(), 1);
+		assert_eq!(
+			report.annotations.first().unwrap().file.as_ref(),
+			"src/foo.rs"
+		);
+	}
+
+	#[test]
+	fn real_path_case() {
+		let dir = std::env::temp_dir().join(format!("ghannotate-case-{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("Src")).unwrap();
+		std::fs::write(dir.join("Src/Foo.rs"), "").unwrap();
+
+		assert_eq!(
+			super::real_path_case("src/foo.rs", &dir).as_deref(),
+			Some("Src/Foo.rs")
+		);
+		assert_eq!(
+			super::real_path_case("./SRC/FOO.RS", &dir).as_deref(),
+			Some("./Src/Foo.rs")
+		);
+		assert_eq!(super::real_path_case("src/bar.rs", &dir), None);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn output_format_bitbucket() {
+		let cli = parse_cli(&["--output-format=bitbucket", "check"]);
+		let input = [
+			compiler_message("a", "error", "src/lib.rs", 4),
+			compiler_message("a", "error", "src/lib.rs", 4),
+		]
+		.join("\n");
+
+		let mut out = Vec::new();
+		cli.process(input.as_bytes(), &mut out).unwrap();
+		assert_eq!(
+			serde_json::from_slice::<serde_json::Value>(&out).unwrap(),
+			serde_json::json!({
+				"annotations": [{
+					"path": "src/lib.rs",
+					"line": 4,
+					"summary": "error in a",
+					"message": "error in a",
+					"severity": "HIGH",
+					"annotation_type": "BUG",
+				}],
+			})
+		);
+	}
+
+	#[test]
+	fn max_warnings() {
+		let cli = parse_cli(&["--max-warnings=5", "check"]);
+		let warnings = |count| {
+			(1..=count)
+				.map(|line| compiler_message("a", "warning", "src/lib.rs", line))
+				.collect::<Vec<_>>()
+				.join("\n")
+		};
+
+		let report = cli.process(warnings(5).as_bytes(), io::sink()).unwrap();
+		assert!(!cli.should_fail(&report, true, false));
+		let report = cli.process(warnings(6).as_bytes(), io::sink()).unwrap();
+		assert!(cli.should_fail(&report, true, false));
+	}
+
+	#[test]
+	fn max_errors() {
+		let cli = parse_cli(&["--max-errors=1", "check"]);
+		let input = compiler_message("a", "error", "src/lib.rs", 1);
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert!(!cli.should_fail(&report, false, false));
+	}
+
+	#[test]
+	fn doctor() {
+		let dir = std::env::temp_dir().join(format!("ghannotate-doctor-{}", std::process::id()));
+		let crate_dir = dir.join("src");
+		std::fs::create_dir_all(&crate_dir).unwrap();
+		File::create(dir.join("Cargo.toml")).unwrap();
+		let summary = dir.join("summary.md");
+		let env = |var: &str| match var {
+			"GITHUB_ACTIONS" => Some(OsString::from("true")),
+			"GITHUB_WORKSPACE" => Some(dir.join("missing").into_os_string()),
+			"GITHUB_STEP_SUMMARY" => Some(summary.clone().into_os_string()),
+			_ => None,
+		};
+
+		let mut out = Vec::new();
+		let healthy = parse_cli(&["doctor"])
+			.doctor(env, &crate_dir, &mut out)
+			.unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		assert!(!healthy);
+		assert!(out.contains("ok: GITHUB_ACTIONS is set\n"));
+		assert!(out.contains("error: GITHUB_WORKSPACE is not a directory"));
+		assert!(out.contains("ok: Cargo manifest found"));
+		assert!(out.contains("ok: GITHUB_STEP_SUMMARY is writable"));
+		assert!(out.ends_with(
+			"::notice::This test annotation was emitted by `cargo ghannotate doctor`\n"
+		));
+	}
+
+	#[test]
+	fn annotate_first_line_only() {
+		let input = serde_json::json!({
+			"reason": "compiler-message",
+			"package_id": "a",
+			"message": {
+				"message": "this function has too many lines",
+				"level": "warning",
+				"spans": [{
+					"file_name": "src/lib.rs",
+					"line_start": 10,
+					"line_end": 110,
+					"column_start": 1,
+					"column_end": 2,
+					"is_primary": true,
+				}],
+				"rendered": null,
+			},
+		})
+		.to_string();
+
+		let mut out = Vec::new();
+		parse_cli(&["--annotate-first-line-only", "check"])
+			.process(input.as_bytes(), &mut out)
+			.unwrap();
+		assert!(String::from_utf8(out)
+			.unwrap()
+			.starts_with("::warning file=src/lib.rs,line=10,endLine=10::"));
+
+		let mut out = Vec::new();
+		parse_cli(&["--annotate-first-line-only=200", "check"])
+			.process(input.as_bytes(), &mut out)
+			.unwrap();
+		assert!(String::from_utf8(out)
+			.unwrap()
+			.starts_with("::warning file=src/lib.rs,line=10,endLine=110::"));
+	}
+
+	#[test]
+	fn max_span_lines() {
+		let span = |file_name: &str, line_end: usize| {
+			serde_json::json!({
+				"reason": "compiler-message",
+				"package_id": "a",
+				"message": {
+					"message": "unused import",
+					"level": "warning",
+					"spans": [{
+						"file_name": file_name,
+						"line_start": 1,
+						"line_end": line_end,
+						"column_start": 1,
+						"column_end": 2,
+						"is_primary": true,
+					}],
+					"rendered": null,
+				},
+			})
+			.to_string()
+		};
+		let cli = parse_cli(&["--max-span-lines=50", "check"]);
+
+		let mut out = Vec::new();
+		let report = cli
+			.process(span("src/lib.rs", 5000).as_bytes(), &mut out)
+			.unwrap();
+		assert!(String::from_utf8(out)
+			.unwrap()
+			.starts_with("::warning file=src/lib.rs,line=1,endLine=1::"));
+		assert!(matches!(
+			&report.summaries[..],
+			[Summary::Diagnostic {
+				location: Some((_, 1, 1)),
+				..
+			}]
+		));
+
+		let mut out = Vec::new();
+		cli.process(span("src/lib.rs", 50).as_bytes(), &mut out)
+			.unwrap();
+		assert!(String::from_utf8(out)
+			.unwrap()
+			.starts_with("::warning file=src/lib.rs,line=1,endLine=50::"));
+
+		let path = std::env::temp_dir().join(format!("ghannotate-span-{}.rs", std::process::id()));
+		std::fs::write(&path, "use a;\n".repeat(100)).unwrap();
+		let mut out = Vec::new();
+		let report = cli
+			.process(span(&path.to_string_lossy(), 100).as_bytes(), &mut out)
+			.unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert!(out.is_empty());
+		assert!(report.annotations.is_empty());
+		assert_eq!(report.summaries.len(), 1);
+	}
+
+	#[test]
+	fn prefer_rendered_spans() {
+		let input = serde_json::json!({
+			"reason": "compiler-message",
+			"package_id": "a",
+			"message": {
+				"message": "unused variable: `x`",
+				"level": "warning",
+				"spans": [{
+					"file_name": "src/macros.rs",
+					"line_start": 3,
+					"line_end": 3,
+					"column_start": 5,
+					"column_end": 6,
+					"is_primary": true,
+				}],
+				"rendered": "warning: unused variable: `x`\n  --> src/lib.rs:12:9\n   |\n",
+			},
+		})
+		.to_string();
+
+		let report = parse_cli(&["check"])
+			.process(input.as_bytes(), io::sink())
+			.unwrap();
+		let annotation = report.annotations.first().unwrap();
+		assert_eq!(
+			(annotation.file.as_ref(), annotation.line),
+			("src/macros.rs", 3)
+		);
+
+		let report = parse_cli(&["--prefer-rendered-spans", "check"])
+			.process(input.as_bytes(), io::sink())
+			.unwrap();
+		let annotation = report.annotations.first().unwrap();
+		assert_eq!(
+			(annotation.file.as_ref(), annotation.line, annotation.col),
+			("src/lib.rs", 12, Some(9))
+		);
+	}
+
+	#[test]
+	fn concurrency_safe_summary() {
+		let path = std::env::temp_dir().join(format!("ghannotate-summary-{}", std::process::id()));
+		let cli = parse_cli(&["--concurrency-safe-summary", "check"]);
+		let input = (1..=500)
+			.map(|line| compiler_message("a", "warning", "src/lib.rs", line))
+			.collect::<Vec<_>>()
+			.join("\n");
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+
+		let mut expected = Vec::new();
+		cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut expected)
+			.unwrap();
+		std::fs::write(&path, "previous\n").unwrap();
+		// Another invocation holds the lock, so the summary is only appended once it is released
+		let other = File::open(&path).unwrap();
+		other.lock().unwrap();
+		thread::scope(|scope| {
+			let writer = scope.spawn(|| {
+				cli.write_summary_file(&path, &report, &HashMap::new())
+					.unwrap();
+			});
+			thread::sleep(Duration::from_millis(300));
+			assert!(!writer.is_finished());
+			other.unlock().unwrap();
+			writer.join().unwrap();
+		});
+		let summary = std::fs::read(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(summary, [b"previous\n".as_slice(), &expected].concat());
+	}
+
+	#[test]
+	fn sarif_output() {
+		let cli = parse_cli(&["--output-format=sarif", "--emit-sarif-rules", "check"]);
+		let input = (1..=2)
+			.map(|line| {
+				with_code(
+					&compiler_message("a", "warning", "src/lib.rs", line),
+					"unused_variables",
+				)
+			})
+			.collect::<Vec<_>>()
+			.join("\n");
+		let mut out = Vec::new();
+		cli.process(input.as_bytes(), &mut out).unwrap();
+
+		let log: serde_json::Value = serde_json::from_slice(&out).unwrap();
+		assert_eq!(log["runs"][0]["results"].as_array().unwrap().len(), 2);
+		assert_eq!(
+			log["runs"][0]["tool"]["driver"]["rules"]
+				.as_array()
+				.unwrap()
+				.len(),
+			1
+		);
+	}
+
+	#[test]
+	fn reformat_rendered_message() {
+		let rendered = "\
+warning: unused variable: `x`
+ --> src/main.rs:2:9
+  |
+2 |     let x = 5;
+  |         ^ help: if this is intentional, prefix it with an underscore: `_x`
+  |
+  = note: `#[warn(unused_variables)]` on by default
+  = help: remove the variable
+
+";
+		assert_eq!(
+			reformat_rendered(rendered),
+			"\
+warning: unused variable: `x`
+
+`src/main.rs:2:9`
+
+```
+    let x = 5;
+        ^ help: if this is intentional, prefix it with an underscore: `_x`
+```
+
+- note: `#[warn(unused_variables)]` on by default
+- help: remove the variable
+"
+		);
+	}
+
+	#[test]
+	fn package_dirs() {
+		assert_eq!(package_dir("path+file:///work/a#0.1.0"), Some("/work/a"));
+		assert_eq!(
+			package_dir("a 0.1.0 (path+file:///work/a)"),
+			Some("/work/a")
+		);
+		assert_eq!(
+			package_dir("registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"),
+			None
+		);
+	}
+
+	#[test]
+	fn same_path_in_distinct_packages() {
+		let cli = parse_cli(&["check"]);
+		let input = [
+			compiler_message("a 0.1.0 (path+file:///work/a)", "warning", "src/lib.rs", 1),
+			compiler_message("b 0.1.0 (path+file:///work/b)", "warning", "src/lib.rs", 1),
+			compiler_message("b 0.1.0 (path+file:///work/b)", "warning", "src/lib.rs", 1),
+		]
+		.join("\n");
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert_eq!(report.annotations.len(), 2);
+	}
+
+	#[test]
+	fn resolve_in_package_dir() {
+		let dir = std::env::temp_dir().join(format!("ghannotate-package-{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("src")).unwrap();
+		std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+		let json = compiler_message(
+			&format!("path+file://{}#0.1.0", dir.display()),
+			"warning",
+			"src/lib.rs",
+			1,
+		);
+		let (annotation, _summary) = Options::default().annotate(&json).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(Path::new(annotation.file.as_ref()), dir.join("src/lib.rs"));
+	}
+
+	#[test]
+	fn emit_order_severity() {
+		let cli = parse_cli(&["--emit-order=severity", "check"]);
+		let input = [
+			compiler_message("a", "warning", "src/a.rs", 1),
+			compiler_message("a", "error", "src/b.rs", 1),
+			compiler_message("a", "warning", "src/c.rs", 1),
+			compiler_message("a", "error", "src/d.rs", 1),
+		]
+		.join("\n");
+		let mut out = Vec::new();
+		cli.process(input.as_bytes(), &mut out).unwrap();
+
+		let lines = String::from_utf8(out).unwrap();
+		let kinds = lines
+			.lines()
+			.map(|line| line.split_once(' ').unwrap().0)
+			.collect::<Vec<_>>();
+		assert_eq!(kinds, ["::error", "::error", "::warning", "::warning"]);
+		assert!(lines.find("src/b.rs").unwrap() < lines.find("src/d.rs").unwrap());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn relativize_symlinks() {
+		let dir = std::env::temp_dir().join(format!("ghannotate-symlink-{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("real/src")).unwrap();
+		std::fs::write(dir.join("real/src/lib.rs"), "").unwrap();
+		std::os::unix::fs::symlink(dir.join("real"), dir.join("link")).unwrap();
+
+		let file = dir.join("real/src/lib.rs");
+		let linked_file = dir.join("link/src/lib.rs");
+		let root = dir.join("link");
+		let relative = relativize(&file, &root, true);
+		let linked_relative = relativize(&linked_file, &dir.join("real"), true);
+		let lexical = relativize(&file, &root, false);
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(relative, Some(PathBuf::from("src/lib.rs")));
+		assert_eq!(linked_relative, Some(PathBuf::from("src/lib.rs")));
+		assert_eq!(lexical, None);
+		assert_eq!(
+			relativize(
+				Path::new("/missing/src/lib.rs"),
+				Path::new("/missing"),
+				true
+			),
+			Some(PathBuf::from("src/lib.rs"))
+		);
+	}
+
+	#[test]
+	fn emit_empty_success_annotation() {
+		let cli = parse_cli(&["--emit-empty-success-annotation", "check"]);
+
+		let report = cli.process(io::empty(), io::sink()).unwrap();
+		let mut out = Vec::new();
+		cli.emit_success(&report, &mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"::notice::All checks passed\n"
+		);
+		assert!(!cli.should_fail(&report, true, false));
+
+		let input = compiler_message("a", "warning", "src/lib.rs", 1);
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let mut out = Vec::new();
+		cli.emit_success(&report, &mut out).unwrap();
+		assert!(out.is_empty());
+
+		let cli = parse_cli(&["check"]);
+		let report = cli.process(io::empty(), io::sink()).unwrap();
+		let mut out = Vec::new();
+		cli.emit_success(&report, &mut out).unwrap();
+		assert!(out.is_empty());
+	}
+
+	#[test]
+	fn summary_flush_interval() {
+		let path = std::env::temp_dir().join(format!("ghannotate-flush-{}", std::process::id()));
+		let cli = parse_cli(&["--summary-flush-interval=0s", "check"]);
+		let mut report = Report::new();
+		report.summary_flush = Some(SummaryFlush {
+			path: path.clone(),
+			interval: Duration::ZERO,
+			flushed_at: Instant::now(),
+		});
+		let input = [
+			compiler_message("a", "warning", "src/lib.rs", 1),
+			compiler_message("a", "error", "src/lib.rs", 2),
+		]
+		.join("\n");
+
+		// The run is interrupted before the complete summary is written
+		cli.process_into(input.as_bytes(), &mut report, &mut io::sink())
+			.unwrap();
+		let partial = std::fs::read_to_string(&path).unwrap();
+		assert!(partial.starts_with("> :hourglass: **Partial summary:**"));
+		assert!(partial.contains("`src/lib.rs:1`"));
+		assert!(partial.contains("`src/lib.rs:2`"));
+
+		cli.write_summary_file(&path, &report, &HashMap::new())
+			.unwrap();
+		let complete = std::fs::read_to_string(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert!(!complete.contains("Partial summary"));
+	}
+
+	#[test]
+	fn location_format() {
+		let input = compiler_message("a", "warning", "src/lib.rs", 3);
+		for (format, location) in [
+			("default", "`src/lib.rs:3`"),
+			("rustc", "`src/lib.rs:3:1`"),
+			("msvc", "`src/lib.rs(3,1)`"),
+		] {
+			let cli = parse_cli(&[&format!("--location-format={format}"), "check"]);
+			let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+			let mut summary = Vec::new();
+			cli.write_diagnostic_summary(&report.summaries, &HashMap::new(), &mut summary)
+				.unwrap();
+			assert!(String::from_utf8(summary)
+				.unwrap()
+				.contains(&format!("|{location}|")));
+		}
+	}
+
+	#[test]
+	fn conflicting_cargo_args() {
+		let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+		assert!(cargo_args_warnings(&args(&["--all-targets", "--timings"])).is_empty());
+		assert!(cargo_args_warnings(&args(&["--message-format=json-diagnostic-short"])).is_empty());
+		assert!(cargo_args_warnings(&args(&["--message-format", "json"])).is_empty());
+		assert!(cargo_args_warnings(&args(&["--", "--unit-graph"])).is_empty());
+
+		let warnings = cargo_args_warnings(&args(&["--unit-graph", "--message-format=short"]));
+		assert_eq!(warnings.len(), 2);
+		assert!(warnings[0].starts_with("`--unit-graph` makes Cargo output a plan"));
+		assert!(warnings[1].starts_with("`--message-format=short` conflicts"));
+	}
+
+	#[test]
+	fn package_names_from_ids() {
+		assert_eq!(package_name("a 0.1.0 (path+file:///work/a)"), "a");
+		assert_eq!(package_name("path+file:///work/a#0.1.0"), "a");
+		assert_eq!(package_name("path+file:///work/crates/a#b@0.1.0"), "b");
+		assert_eq!(
+			package_name("registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"),
+			"serde"
+		);
+	}
+
+	#[test]
+	fn package_identities() {
+		use crate::cargo::package_identity;
+
+		assert_eq!(
+			package_identity("path+file:///home/runner/work/r/r#a@0.1.0"),
+			package_identity("path+file:///Users/runner/work/r/r#a@0.1.0")
+		);
+		assert_eq!(package_identity("path+file:///work/a#0.1.0"), "a@0.1.0");
+		assert_eq!(package_identity("a 0.1.0 (path+file:///work/a)"), "a@0.1.0");
+		assert_ne!(
+			package_identity("path+file:///work/r#a@0.1.0"),
+			package_identity("path+file:///work/r#b@0.1.0")
+		);
+		assert_eq!(
+			package_identity("registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"),
+			"registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"
+		);
+	}
+
+	#[test]
+	fn summary_include_passing_crates() {
+		let cli = parse_cli(&["--summary-include-passing-crates", "check"]);
+		let artifact = |package_id: &str| {
+			serde_json::json!({
+				"reason": "compiler-artifact",
+				"package_id": package_id,
+				"target": { "name": "lib" },
+				"filenames": [],
+			})
+			.to_string()
+		};
+		let input = [
+			compiler_message("path+file:///work/a#0.1.0", "warning", "a/src/lib.rs", 1),
+			compiler_message("path+file:///work/a#0.1.0", "error", "a/src/lib.rs", 2),
+			compiler_message("path+file:///work/a#0.1.0", "warning", "a/src/lib.rs", 3),
+			artifact("path+file:///work/a#0.1.0"),
+			artifact("path+file:///work/b#0.1.0"),
+			artifact("registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"),
+		]
+		.join("\n");
+
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let summary = String::from_utf8(cli.summary(&report, &HashMap::new()).unwrap()).unwrap();
+		assert!(summary.contains("|`a`|1|2|0|\n"));
+		assert!(summary.contains("|`b`|0|0|0|\n"));
+		assert!(!summary.contains("serde"));
+
+		let package_names =
+			HashMap::from([("path+file:///work/a#0.1.0".to_owned(), "a".to_owned())]);
+		let summary = String::from_utf8(cli.summary(&report, &package_names).unwrap()).unwrap();
+		assert!(summary.contains("|`a`|1|2|0|\n"));
+		assert!(!summary.contains("|`b`|"));
+		assert!(!summary.contains("serde"));
+
+		let cli = parse_cli(&["check"]);
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		let summary = String::from_utf8(cli.summary(&report, &HashMap::new()).unwrap()).unwrap();
+		assert!(!summary.contains("# Crates"));
+	}
+
+	#[test]
+	fn dedup_window() {
+		let cli = parse_cli(&["--dedup-window=3", "check"]);
+		let input = (1..=12)
+			.map(|line| {
+				with_code(
+					&compiler_message("a", "warning", "src/lib.rs", line),
+					"clippy::style",
+				)
+			})
+			.chain([
+				with_code(
+					&compiler_message("a", "warning", "src/lib.rs", 16),
+					"clippy::style",
+				),
+				with_code(
+					&compiler_message("a", "warning", "src/lib.rs", 17),
+					"clippy::other",
+				),
+				compiler_message("a", "warning", "src/lib.rs", 18),
+				compiler_message("a", "warning", "src/lib.rs", 19),
+			])
+			.collect::<Vec<_>>()
+			.join("\n");
+
+		let mut out = Vec::new();
+		let report = cli.process(input.as_bytes(), &mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"\
+::warning file=src/lib.rs,line=1,endLine=12,title=warning in a × 12::warning in a
+::warning file=src/lib.rs,line=16,endLine=16,col=1,endColumn=2::warning in a
+::warning file=src/lib.rs,line=17,endLine=17,col=1,endColumn=2::warning in a
+::warning file=src/lib.rs,line=18,endLine=18,col=1,endColumn=2::warning in a
+::warning file=src/lib.rs,line=19,endLine=19,col=1,endColumn=2::warning in a
+"
+		);
+		assert_eq!(report.summaries.len(), 16);
+
+		// Other annotations in between do not break the collapsed annotations
+		let input = [
+			(1, "clippy::a"),
+			(2, "clippy::b"),
+			(3, "clippy::a"),
+			(4, "clippy::a"),
+		]
+		.map(|(line, code)| with_code(&compiler_message("a", "warning", "src/lib.rs", line), code))
+		.join("\n");
+		let mut out = Vec::new();
+		cli.process(input.as_bytes(), &mut out).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"\
+::warning file=src/lib.rs,line=1,endLine=4,title=warning in a × 3::warning in a
+::warning file=src/lib.rs,line=2,endLine=2,col=1,endColumn=2::warning in a
+"
+		);
+
+		for (format, pointer) in [("bitbucket", "/annotations"), ("sarif", "/runs/0/results")] {
+			let cli = parse_cli(&[
+				"--dedup-window=3",
+				&format!("--output-format={format}"),
+				"check",
+			]);
+			let mut out = Vec::new();
+			cli.process(input.as_bytes(), &mut out).unwrap();
+			let out = serde_json::from_slice::<serde_json::Value>(&out).unwrap();
+			assert_eq!(out.pointer(pointer).unwrap().as_array().unwrap().len(), 2);
+		}
+	}
+
+	#[test]
+	fn unexplained_cargo_failure_fails() {
+		let cli = parse_cli(&["--allow-warnings", "check"]);
+
+		let report = cli.process(io::empty(), io::sink()).unwrap();
+		assert!(cli.should_fail(&report, false, false));
+	}
+
+	#[test]
+	fn exit_zero_even_if_cargo_fails() {
+		let cli = parse_cli(&["--exit-zero-on-no-diagnostics-even-if-cargo-fails", "check"]);
+
+		let report = cli.process(io::empty(), io::sink()).unwrap();
+		assert!(!cli.should_fail(&report, false, false));
+
+		let input = compiler_message("a", "error", "src/lib.rs", 1);
+		let report = cli.process(input.as_bytes(), io::sink()).unwrap();
+		assert!(cli.should_fail(&report, false, false));
 	}
 }
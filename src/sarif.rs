@@ -0,0 +1,321 @@
+//! Provides structures to output a SARIF log (e.g. for GitHub code scanning)
+
+use crate::{
+	cargo::is_error_code,
+	github::{Annotation, AnnotationKind},
+};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// A [SARIF 2.1.0](https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html) log
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SarifLog<'a> {
+	/// Version of the SARIF format
+	version: &'static str,
+	/// URI of the JSON schema of the SARIF format
+	#[serde(rename = "$schema")]
+	schema: &'static str,
+	/// Runs of analysis tools (a single one here)
+	runs: [Run<'a>; 1],
+}
+impl<'a> SarifLog<'a> {
+	/// Creates a log of `results`
+	///
+	/// If `emit_rules` is `true`, the driver lists a rule for each distinct code of `results`.
+	pub(crate) fn new(results: &'a [SarifResult], emit_rules: bool) -> Self {
+		let rules = if emit_rules {
+			results
+				.iter()
+				.filter_map(|result| result.rule_id.as_deref())
+				.collect::<BTreeSet<_>>()
+				.into_iter()
+				.map(Rule::new)
+				.collect()
+		} else {
+			Vec::new()
+		};
+
+		Self {
+			version: "2.1.0",
+			schema: "https://json.schemastore.org/sarif-2.1.0.json",
+			runs: [Run {
+				tool: Tool {
+					driver: Driver {
+						name: env!("CARGO_PKG_NAME"),
+						version: env!("CARGO_PKG_VERSION"),
+						information_uri: env!("CARGO_PKG_REPOSITORY"),
+						rules,
+					},
+				},
+				results,
+			}],
+		}
+	}
+}
+
+/// A run of an analysis tool
+#[derive(Debug, Clone, Serialize)]
+struct Run<'a> {
+	/// The analysis tool
+	tool: Tool<'a>,
+	/// Results of the analysis
+	results: &'a [SarifResult],
+}
+
+/// An analysis tool
+#[derive(Debug, Clone, Serialize)]
+struct Tool<'a> {
+	/// Main component of the tool
+	driver: Driver<'a>,
+}
+
+/// Main component of an analysis tool
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver<'a> {
+	/// Name of the tool
+	name: &'static str,
+	/// Version of the tool
+	version: &'static str,
+	/// URI of the home page of the tool
+	information_uri: &'static str,
+	/// Rules checked by the tool
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	rules: Vec<Rule<'a>>,
+}
+
+/// Metadata of a lint or an error code
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Rule<'a> {
+	/// Code of the diagnostic (e.g. `E0308` or `clippy::needless_return`)
+	id: &'a str,
+	/// Short description of the rule
+	short_description: Message<'a>,
+	/// URI of the documentation of the rule
+	help_uri: String,
+}
+impl<'a> Rule<'a> {
+	/// Creates the rule of the diagnostic `code`
+	fn new(code: &'a str) -> Self {
+		let (name, help_uri) = if let Some(lint) = code.strip_prefix("clippy::") {
+			(
+				lint,
+				format!("https://rust-lang.github.io/rust-clippy/master/index.html#{lint}"),
+			)
+		} else if is_error_code(code) {
+			(
+				code,
+				format!("https://doc.rust-lang.org/error_codes/{code}.html"),
+			)
+		} else {
+			(
+				code,
+				"https://doc.rust-lang.org/rustc/lints/listing/index.html".to_owned(),
+			)
+		};
+
+		Self {
+			id: code,
+			short_description: Message { text: name },
+			help_uri,
+		}
+	}
+}
+
+/// A textual message
+#[derive(Debug, Clone, Copy, Serialize)]
+struct Message<'a> {
+	/// Plain text of the message
+	text: &'a str,
+}
+
+/// A result of the analysis (i.e. an annotation)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SarifResult {
+	/// Code of the diagnostic
+	#[serde(skip_serializing_if = "Option::is_none")]
+	rule_id: Option<String>,
+	/// Severity of the result
+	level: Level,
+	/// Message of the result
+	message: OwnedMessage,
+	/// Locations of the result (none if the annotation has no location)
+	locations: Vec<Location>,
+}
+impl SarifResult {
+	/// Creates the result of `annotation`, emitted for a diagnostic of `code`
+	pub(crate) fn new(annotation: &Annotation, code: Option<&str>) -> Self {
+		Self {
+			rule_id: code.map(str::to_owned),
+			level: annotation.kind.into(),
+			message: OwnedMessage {
+				text: annotation.message.trim().to_owned(),
+			},
+			locations: annotation
+				.has_location()
+				.then(|| Location {
+					physical_location: PhysicalLocation {
+						artifact_location: ArtifactLocation {
+							uri: annotation.file.replace('\\', "/"),
+						},
+						region: Region {
+							start_line: annotation.line,
+							start_column: annotation.col,
+							end_line: annotation.end_line,
+							end_column: annotation.end_column,
+						},
+					},
+				})
+				.into_iter()
+				.collect(),
+		}
+	}
+}
+
+/// A textual message, owned by a [`SarifResult`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct OwnedMessage {
+	/// Plain text of the message
+	text: String,
+}
+
+#[allow(clippy::missing_docs_in_private_items)]
+/// Severity of a [`SarifResult`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+	Note,
+	Warning,
+	Error,
+}
+impl From<AnnotationKind> for Level {
+	#[inline]
+	fn from(kind: AnnotationKind) -> Self {
+		match kind {
+			AnnotationKind::Notice => Self::Note,
+			AnnotationKind::Warning => Self::Warning,
+			AnnotationKind::Error => Self::Error,
+		}
+	}
+}
+
+/// Location of a [`SarifResult`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+	/// Location in a file
+	physical_location: PhysicalLocation,
+}
+
+/// Location in a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+	/// The file
+	artifact_location: ArtifactLocation,
+	/// The region of the file
+	region: Region,
+}
+
+/// A file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ArtifactLocation {
+	/// Path of the file, relative to the root of the repository
+	uri: String,
+}
+
+/// A region of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+	/// First line of the region (1-based)
+	start_line: usize,
+	/// First column of the region (1-based)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	start_column: Option<usize>,
+	/// Last line of the region (1-based, inclusive)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	end_line: Option<usize>,
+	/// Last column of the region (1-based, exclusive)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	end_column: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::borrow::Cow;
+
+	fn annotation(line: usize) -> Annotation<'static> {
+		Annotation {
+			kind: AnnotationKind::Warning,
+			file: Cow::Borrowed("src/main.rs"),
+			line,
+			end_line: Some(line),
+			col: Some(5),
+			end_column: Some(10),
+			title: None,
+			message: Cow::Borrowed("unused variable\n"),
+			package: None,
+			code: None,
+		}
+	}
+
+	#[test]
+	fn sarif_result() {
+		assert_eq!(
+			serde_json::to_value(SarifResult::new(&annotation(3), Some("unused_variables")))
+				.unwrap(),
+			serde_json::json!({
+				"ruleId": "unused_variables",
+				"level": "warning",
+				"message": { "text": "unused variable" },
+				"locations": [{
+					"physicalLocation": {
+						"artifactLocation": { "uri": "src/main.rs" },
+						"region": { "startLine": 3, "startColumn": 5, "endLine": 3, "endColumn": 10 },
+					},
+				}],
+			})
+		);
+	}
+
+	#[test]
+	fn sarif_rules() {
+		let results = [
+			SarifResult::new(&annotation(1), Some("clippy::needless_return")),
+			SarifResult::new(&annotation(2), Some("E0308")),
+			SarifResult::new(&annotation(3), Some("clippy::needless_return")),
+			SarifResult::new(&annotation(4), Some("unused_variables")),
+			SarifResult::new(&annotation(5), None),
+		];
+
+		let log = serde_json::to_value(SarifLog::new(&results, true)).unwrap();
+		assert_eq!(
+			log["runs"][0]["tool"]["driver"]["rules"],
+			serde_json::json!([
+				{
+					"id": "E0308",
+					"shortDescription": { "text": "E0308" },
+					"helpUri": "https://doc.rust-lang.org/error_codes/E0308.html",
+				},
+				{
+					"id": "clippy::needless_return",
+					"shortDescription": { "text": "needless_return" },
+					"helpUri": "https://rust-lang.github.io/rust-clippy/master/index.html#needless_return",
+				},
+				{
+					"id": "unused_variables",
+					"shortDescription": { "text": "unused_variables" },
+					"helpUri": "https://doc.rust-lang.org/rustc/lints/listing/index.html",
+				},
+			])
+		);
+		assert_eq!(log["runs"][0]["results"].as_array().unwrap().len(), 5);
+
+		let log = serde_json::to_value(SarifLog::new(&results, false)).unwrap();
+		assert!(log["runs"][0]["tool"]["driver"].get("rules").is_none());
+	}
+}